@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use git2::{IndexAddOption, Repository, Signature};
+use tracing::info;
+
+use crate::export::{self, ExportArgs, Folders, Summary};
+use crate::Page;
+
+/// Author of the commits when no git identity is configured.
+const AUTHOR_NAME: &str = "hackmd-search";
+const AUTHOR_EMAIL: &str = "hackmd-search@localhost";
+
+/// Message of a commit: a summary line, followed by the notes that were
+/// added, changed and removed.
+fn message(summary: &Summary) -> String {
+    let mut message = format!(
+        "Synchronize notes: {} added, {} changed, {} removed\n",
+        summary.added.len(),
+        summary.changed.len(),
+        summary.removed.len()
+    );
+    for (heading, notes) in [
+        ("Added", &summary.added),
+        ("Changed", &summary.changed),
+        ("Removed", &summary.removed),
+    ] {
+        if notes.is_empty() {
+            continue;
+        }
+        message.push_str(&format!("\n{}:\n", heading));
+        for note in notes {
+            message.push_str(&format!("- {}\n", note));
+        }
+    }
+    message
+}
+
+/// Mirror the notes as Markdown files into a git repository, created if
+/// needed, and commit the changes.
+pub fn update(page_list: &[Page], dir: &Path, server_url: &str) -> anyhow::Result<()> {
+    let repo = match Repository::open(dir) {
+        Ok(repo) => repo,
+        Err(_) => {
+            info!("Creating the git archive in {}", dir.display());
+            Repository::init(dir)?
+        }
+    };
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::anyhow!("{} is a bare git repository", dir.display()))?
+        .to_path_buf();
+
+    let args = ExportArgs {
+        dir: workdir,
+        folders: Folders::Tag,
+    };
+    let summary = export::run(page_list, server_url, &args)?;
+
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+    if parent.as_ref().map(|parent| parent.tree_id()) == Some(tree.id()) {
+        info!("No changes in the git archive");
+        return Ok(());
+    }
+
+    let signature = match repo.signature() {
+        Ok(signature) => signature,
+        Err(_) => Signature::now(AUTHOR_NAME, AUTHOR_EMAIL)?,
+    };
+    let parents: Vec<_> = parent.iter().collect();
+    let commit = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message(&summary),
+        &tree,
+        &parents,
+    )?;
+    info!("Committed {} to the git archive", commit);
+    Ok(())
+}
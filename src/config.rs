@@ -0,0 +1,24 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::meilisearch::IndexSettings;
+
+/// Settings read from the TOML configuration file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// HackMD API token.
+    pub token: Option<String>,
+    /// Settings of the Meilisearch index, replacing the default profile.
+    pub settings: Option<IndexSettings>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid configuration file {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+/// Service name of the secrets stored in the OS keyring.
+const KEYRING_SERVICE: &str = "hackmd-search";
+/// Keyring user name of the HackMD API token.
+const KEYRING_TOKEN_USER: &str = "token";
+
+/// Where a secret was read from.
+#[derive(Debug)]
+pub enum Source {
+    CommandLine,
+    File(PathBuf),
+    Environment(&'static str),
+    Config,
+    Keyring,
+    Prompt,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CommandLine => write!(f, "command line"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Environment(var) => write!(f, "environment variable {}", var),
+            Source::Config => write!(f, "configuration file"),
+            Source::Keyring => write!(f, "OS keyring"),
+            Source::Prompt => write!(f, "prompt"),
+        }
+    }
+}
+
+/// Secret value, such as a password or an API token, and where it came from.
+///
+/// The value is never printed, neither with `{}` nor with `{:?}`.
+pub struct Secret {
+    value: String,
+    pub source: Source,
+}
+
+impl Secret {
+    fn new(value: String, source: Source) -> Self {
+        Secret { value, source }
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("value", &"<redacted>")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/// Places where the credentials can be looked up, by order of precedence:
+/// command line, file, environment variable, configuration file, OS keyring
+/// and finally an interactive prompt.
+pub struct Lookup<'a> {
+    pub token: Option<&'a str>,
+    pub token_file: Option<&'a Path>,
+    pub config_token: Option<&'a str>,
+    pub user: Option<&'a str>,
+    pub password_file: Option<&'a Path>,
+    pub keyring: bool,
+}
+
+impl fmt::Debug for Lookup<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |secret: Option<&str>| secret.map(|_| "<redacted>");
+        f.debug_struct("Lookup")
+            .field("token", &redacted(self.token))
+            .field("token_file", &self.token_file)
+            .field("config_token", &redacted(self.config_token))
+            .field("user", &self.user)
+            .field("password_file", &self.password_file)
+            .field("keyring", &self.keyring)
+            .finish()
+    }
+}
+
+fn read_secret_file(path: &Path) -> anyhow::Result<Secret> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("unable to read {}: {}", path.display(), e))?;
+    let value = content.trim_end_matches(&['\r', '\n'][..]).to_string();
+    anyhow::ensure!(!value.is_empty(), "{} is empty", path.display());
+
+    Ok(Secret::new(value, Source::File(path.to_path_buf())))
+}
+
+fn from_env(var: &'static str) -> Option<Secret> {
+    std::env::var(var)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| Secret::new(value, Source::Environment(var)))
+}
+
+/// Look up a secret in the OS keyring (e.g. the Secret Service over D-Bus).
+///
+/// A missing entry or an unavailable keyring is not an error, the next source
+/// is tried instead.
+fn from_keyring(user: &str) -> Option<Secret> {
+    let password =
+        keyring::Entry::new(KEYRING_SERVICE, user).and_then(|entry| entry.get_password());
+
+    match password {
+        Ok(value) => Some(Secret::new(value, Source::Keyring)),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            warn!("OS keyring unavailable: {}", e);
+            None
+        }
+    }
+}
+
+impl<'a> Lookup<'a> {
+    /// Find the HackMD API token, if any.
+    pub fn token(&self) -> anyhow::Result<Option<Secret>> {
+        if let Some(token) = self.token {
+            return Ok(Some(Secret::new(token.to_string(), Source::CommandLine)));
+        }
+        if let Some(path) = self.token_file {
+            return read_secret_file(path).map(Some);
+        }
+        if let Some(secret) = from_env("HACKMD_TOKEN") {
+            return Ok(Some(secret));
+        }
+        if let Some(token) = self.config_token {
+            return Ok(Some(Secret::new(token.to_string(), Source::Config)));
+        }
+        if self.keyring {
+            return Ok(from_keyring(KEYRING_TOKEN_USER));
+        }
+
+        Ok(None)
+    }
+
+    /// Find the e-mail and password used to log in, prompting for them as
+    /// a last resort.
+    pub fn login(&self) -> anyhow::Result<(String, Secret)> {
+        let user = match self.user {
+            Some(user) => user.to_string(),
+            None => {
+                print!("HackMD user: ");
+                std::io::stdout().flush()?;
+                let mut user = String::new();
+                std::io::stdin()
+                    .read_line(&mut user)
+                    .expect("error: unable to read user input");
+                user.trim().to_string()
+            }
+        };
+
+        let password = if let Some(path) = self.password_file {
+            read_secret_file(path)?
+        } else if let Some(secret) = from_env("HACKMD_PASSWORD") {
+            secret
+        } else if let Some(secret) = self.keyring.then(|| from_keyring(&user)).flatten() {
+            secret
+        } else {
+            let password = rpassword::prompt_password("HackMD password: ")?;
+            Secret::new(password, Source::Prompt)
+        };
+
+        Ok((user, password))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_secrets() {
+        let lookup = Lookup {
+            token: Some("command-line-token"),
+            token_file: None,
+            config_token: Some("config-token"),
+            user: Some("user@example.com"),
+            password_file: None,
+            keyring: false,
+        };
+        let secret = Secret::new("hunter2".to_string(), Source::Prompt);
+
+        let debug = format!("{:?} {:?} {}", lookup, secret, secret);
+
+        assert!(!debug.contains("command-line-token"));
+        assert!(!debug.contains("config-token"));
+        assert!(!debug.contains("hunter2"));
+        assert!(debug.contains("user@example.com"));
+    }
+}
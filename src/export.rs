@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+use tracing::{debug, info, warn};
+
+use crate::frontmatter::split_front_matter;
+use crate::Page;
+
+/// File of the export listing the file of each note, so that the next exports
+/// update and remove the same files.
+const MANIFEST: &str = ".hackmd-export.json";
+
+/// Maximum number of characters of the file and folder names.
+const MAX_NAME_CHARS: usize = 80;
+
+/// How the notes are organized in folders.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Folders {
+    /// One folder per tag, named after the first tag of the notes. Notes
+    /// without tags are at the root.
+    Tag,
+    /// All the notes at the root.
+    Flat,
+}
+
+/// Options of the `export` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Directory to export the notes to.
+    pub dir: PathBuf,
+
+    /// How the notes are organized in folders.
+    #[clap(long, arg_enum, default_value = "tag")]
+    pub folders: Folders,
+}
+
+/// Notes added, changed and removed by an export, as `<TITLE> (<ID>)`.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Lowercase name made of the letters and digits of a text, separated by
+/// dashes.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(MAX_NAME_CHARS).collect();
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Files a note can be exported to, relative to the export directory: named
+/// after its title, or after its id if the title is taken by another note.
+fn candidates(page: &Page, folders: Folders) -> [String; 2] {
+    let folder = match folders {
+        Folders::Tag => page.tags.first().map(|tag| slugify(tag)),
+        Folders::Flat => None,
+    };
+    let prefix = match folder {
+        Some(folder) if !folder.is_empty() => format!("{}/", folder),
+        _ => String::new(),
+    };
+    match slugify(&page.title) {
+        slug if slug.is_empty() => [
+            format!("{}{}.md", prefix, page.id),
+            format!("{}{}.md", prefix, page.id),
+        ],
+        slug => [
+            format!("{}{}.md", prefix, slug),
+            format!("{}{}-{}.md", prefix, slug, page.id),
+        ],
+    }
+}
+
+/// Files of the notes, relative to the export directory.
+///
+/// The files are stable across runs: notes keep the file they were previously
+/// exported to as long as their title and tags do not change, and notes that
+/// could not be downloaded keep their previous file.
+fn assign_paths(
+    page_list: &[Page],
+    folders: Folders,
+    previous: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut pages: Vec<&Page> = page_list.iter().filter(|page| !page.deleted).collect();
+    pages.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut paths = BTreeMap::new();
+    let mut taken = HashSet::new();
+    for page in &pages {
+        let path = match previous.get(&page.id) {
+            Some(path) => path,
+            None => continue,
+        };
+        if page.content.is_none() || candidates(page, folders).contains(path) {
+            taken.insert(path.clone());
+            paths.insert(page.id.clone(), path.clone());
+        }
+    }
+
+    for page in pages {
+        if page.content.is_none() || paths.contains_key(&page.id) {
+            continue;
+        }
+        let [by_title, by_id] = candidates(page, folders);
+        let path = if taken.contains(&by_title) {
+            by_id
+        } else {
+            by_title
+        };
+        taken.insert(path.clone());
+        paths.insert(page.id.clone(), path);
+    }
+
+    paths
+}
+
+/// Markdown file of a note: its content, with the id, title, last change time
+/// and URL of the note added to its front matter.
+fn render(page: &Page, server_url: &str) -> anyhow::Result<String> {
+    let content = page.content.as_deref().unwrap_or_default();
+
+    let mut front_matter = Mapping::new();
+    let mut insert = |key: &str, value: &str| {
+        front_matter.insert(Value::from(key), Value::from(value));
+    };
+    insert("id", &page.id);
+    insert("title", &page.title);
+    insert("lastchangeAt", &page.lastchange_at);
+    insert("url", &format!("{}/{}", server_url, page.id));
+
+    let body = match split_front_matter(content) {
+        Some((yaml, body)) => match serde_yaml::from_str::<Option<Mapping>>(yaml) {
+            Ok(entries) => {
+                for (key, value) in entries.into_iter().flatten() {
+                    if !front_matter.contains_key(&key) {
+                        front_matter.insert(key, value);
+                    }
+                }
+                body
+            }
+            // Kept as is in the content.
+            Err(e) => {
+                debug!("Invalid front matter in note {}: {}", page.id, e);
+                content
+            }
+        },
+        None => content,
+    };
+
+    let yaml = serde_yaml::to_string(&front_matter)?;
+    let yaml = yaml.trim_start_matches("---\n").trim_end();
+    Ok(format!("---\n{}\n---\n{}", yaml, body))
+}
+
+/// Whether a path of the manifest stays inside the export directory.
+fn is_relative(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Export the notes as Markdown files. Exporting again to the same directory
+/// updates the files of the notes that changed and removes the files of the
+/// notes that were deleted or moved, without touching any other file.
+pub fn run(page_list: &[Page], server_url: &str, args: &ExportArgs) -> anyhow::Result<Summary> {
+    let manifest = args.dir.join(MANIFEST);
+    let previous: BTreeMap<String, String> = match fs::read(&manifest) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let previous: BTreeMap<String, String> = previous
+        .into_iter()
+        .filter(|(id, path)| {
+            let valid = is_relative(path);
+            if !valid {
+                warn!("Ignoring invalid path of note {} in {}", id, MANIFEST);
+            }
+            valid
+        })
+        .collect();
+
+    let paths = assign_paths(page_list, args.folders, &previous);
+    let mut summary = Summary::default();
+    for page in page_list {
+        let (relative, path) = match paths.get(&page.id) {
+            Some(path) if page.content.is_some() => (path, args.dir.join(path)),
+            _ => continue,
+        };
+        let markdown = render(page, server_url)?;
+        let note = format!("{} ({})", page.title, page.id);
+        match previous.get(&page.id) {
+            None => summary.added.push(note),
+            Some(previous) if previous != relative => summary.changed.push(note),
+            Some(_) if fs::read(&path).ok().as_deref() == Some(markdown.as_bytes()) => continue,
+            Some(_) => summary.changed.push(note),
+        }
+        debug!("Writing note {} to {}", page.id, path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, markdown)?;
+    }
+
+    let titles: HashMap<&str, &str> = page_list
+        .iter()
+        .map(|page| (page.id.as_str(), page.title.as_str()))
+        .collect();
+    let current: HashSet<&String> = paths.values().collect();
+    for (id, path) in &previous {
+        if !paths.contains_key(id) {
+            let title = titles.get(id.as_str()).copied().unwrap_or(path);
+            summary.removed.push(format!("{} ({})", title, id));
+        }
+        // The file may now be the one of another note.
+        if current.contains(path) {
+            continue;
+        }
+        let path = args.dir.join(path);
+        debug!("Removing note {} from {}", id, path.display());
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        // Only succeeds if the folder is now empty.
+        if let Some(parent) = path.parent().filter(|parent| *parent != args.dir) {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+
+    fs::create_dir_all(&args.dir)?;
+    fs::write(&manifest, serde_json::to_vec_pretty(&paths)?)?;
+    info!(
+        "Exported {} notes to {}: {} added, {} changed, {} removed",
+        paths.len(),
+        args.dir.display(),
+        summary.added.len(),
+        summary.changed.len(),
+        summary.removed.len()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str, title: &str, tags: &[&str]) -> Page {
+        Page {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: Some(format!("# {}", title)),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Page::default()
+        }
+    }
+
+    fn paths(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(id, path)| (id.to_string(), path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn notes_are_named_after_their_title_and_first_tag() {
+        let page_list = vec![
+            page("abc", "Meeting Notes: 2022/05", &["Team Meeting", "weekly"]),
+            page("def", "Kubernetes", &[]),
+            page("ghi", "???", &[]),
+        ];
+
+        assert_eq!(
+            assign_paths(&page_list, Folders::Tag, &BTreeMap::new()),
+            paths(&[
+                ("abc", "team-meeting/meeting-notes-2022-05.md"),
+                ("def", "kubernetes.md"),
+                ("ghi", "ghi.md"),
+            ])
+        );
+        assert_eq!(
+            assign_paths(&page_list, Folders::Flat, &BTreeMap::new())["abc"],
+            "meeting-notes-2022-05.md"
+        );
+    }
+
+    #[test]
+    fn title_collisions_use_the_id() {
+        let page_list = vec![page("def", "Notes", &[]), page("abc", "Notes", &[])];
+
+        assert_eq!(
+            assign_paths(&page_list, Folders::Tag, &BTreeMap::new()),
+            paths(&[("abc", "notes.md"), ("def", "notes-def.md")])
+        );
+    }
+
+    #[test]
+    fn paths_are_stable() {
+        let page_list = vec![page("abc", "Notes", &[]), page("def", "Notes", &[])];
+        let previous = paths(&[("abc", "notes-abc.md"), ("def", "notes.md")]);
+
+        assert_eq!(assign_paths(&page_list, Folders::Tag, &previous), previous);
+    }
+
+    #[test]
+    fn renamed_notes_move() {
+        let page_list = vec![page("abc", "Minutes", &["meeting"])];
+        let previous = paths(&[("abc", "notes.md")]);
+
+        assert_eq!(
+            assign_paths(&page_list, Folders::Tag, &previous),
+            paths(&[("abc", "meeting/minutes.md")])
+        );
+    }
+
+    #[test]
+    fn failed_notes_keep_their_path() {
+        let mut failed = page("abc", "Minutes", &[]);
+        failed.content = None;
+        let page_list = vec![failed, page("def", "Notes", &[]), page("ghi", "New", &[])];
+        let previous = paths(&[("abc", "notes.md"), ("def", "notes-def.md")]);
+
+        assert_eq!(
+            assign_paths(&page_list, Folders::Tag, &previous),
+            paths(&[
+                ("abc", "notes.md"),
+                ("def", "notes-def.md"),
+                ("ghi", "new.md"),
+            ])
+        );
+    }
+
+    #[test]
+    fn failed_notes_without_path_and_deleted_notes_are_skipped() {
+        let mut failed = page("abc", "Minutes", &[]);
+        failed.content = None;
+        let mut deleted = page("def", "Notes", &[]);
+        deleted.deleted = true;
+        let previous = paths(&[("def", "notes.md")]);
+
+        assert!(assign_paths(&[failed, deleted], Folders::Tag, &previous).is_empty());
+    }
+}
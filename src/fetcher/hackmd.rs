@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+
+use chrono::{TimeZone, Utc};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use tracing::debug;
+
+use super::{build_client, download_markdown, sync_contents, Auth, Server};
+use crate::sync::SyncSummary;
+use crate::Page;
+
+/// REST API of hackmd.io, hosted on a separate domain.
+const HACKMD_IO_API_URL: &str = "https://api.hackmd.io/v1";
+
+/// Note as returned by the HackMD REST API.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ApiNote {
+    id: String,
+    title: String,
+    created_at: i64,
+    last_changed_at: Option<i64>,
+    #[serde(default)]
+    tags: Vec<String>,
+    content: Option<String>,
+}
+
+impl From<ApiNote> for Page {
+    fn from(note: ApiNote) -> Self {
+        let timestamp = note.last_changed_at.unwrap_or(note.created_at);
+        let lastchange_at = Utc
+            .timestamp_millis_opt(timestamp)
+            .single()
+            .map(|date| date.to_rfc3339())
+            .unwrap_or_default();
+
+        Page {
+            id: note.id,
+            title: note.title,
+            lastchange_at,
+            content: note.content,
+            tags: note.tags,
+            ..Page::default()
+        }
+    }
+}
+
+/// Base URL of the REST API. Self-hosted instances serve it under `/api/v1`.
+fn api_url(server: &Server) -> String {
+    match &server.api_url {
+        Some(url) => url.clone(),
+        None if server.url == super::DEFAULT_SERVER_URL => HACKMD_IO_API_URL.to_string(),
+        None => format!("{server}/api/v1", server = server.url),
+    }
+}
+
+/// Download the notes of a team.
+pub async fn build_database(
+    server: &Server,
+    team: &str,
+    auth: &Auth,
+    previous: &[Page],
+) -> anyhow::Result<(Vec<Page>, SyncSummary)> {
+    match auth {
+        Auth::Token(token) => build_database_from_api(server, team, token.expose(), previous).await,
+        Auth::Login { user, password } => {
+            build_database_from_login(server, team, user, password.expose(), previous).await
+        }
+    }
+}
+
+async fn build_database_from_api(
+    server: &Server,
+    team: &str,
+    token: &str,
+    previous: &[Page],
+) -> anyhow::Result<(Vec<Page>, SyncSummary)> {
+    let mut headers = HeaderMap::new();
+    let mut auth_value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+    auth_value.set_sensitive(true);
+    headers.insert(AUTHORIZATION, auth_value);
+    let client = build_client(headers)?;
+    let api_url = api_url(server);
+
+    // Query
+    // See: https://hackmd.io/@hackmd-api/developer-portal
+    let request_url = format!("{api}/teams/{team}/notes", api = api_url, team = team);
+    let response = client.get(&request_url).send().await?.error_for_status()?;
+    let note_list: Vec<ApiNote> = response.json().await?;
+    let mut page_list: Vec<Page> = note_list.into_iter().map(Page::from).collect();
+
+    let summary = sync_contents(&mut page_list, previous, |id| {
+        let client = &client;
+        let note_url = format!("{api}/notes/{id}", api = api_url, id = id);
+
+        async move {
+            let response = client.get(&note_url).send().await?.error_for_status()?;
+            let note: ApiNote = response.json().await?;
+            Ok(note.content)
+        }
+    })
+    .await;
+
+    Ok((page_list, summary))
+}
+
+async fn build_database_from_login(
+    server: &Server,
+    team: &str,
+    user: &str,
+    password: &str,
+    previous: &[Page],
+) -> anyhow::Result<(Vec<Page>, SyncSummary)> {
+    let client = build_client(HeaderMap::new())?;
+
+    // Get CSRF token
+    // See: https://hackmd.io/@ystl/BkqNtYvrP
+    let response = client.get(&server.url).send().await?;
+    let content = response.text().await?;
+    let re = Regex::new(r#""csrf-token" content="(.+)""#).unwrap();
+    let cap = re
+        .captures_iter(&content)
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no CSRF token found"))?;
+    let csrf_token = String::from(&cap[1]);
+    debug!("Found CSRF token");
+
+    // Login
+    let login_url = format!("{server}/login", server = server.url);
+    let mut params = HashMap::new();
+
+    params.insert("email", user);
+    params.insert("password", password);
+
+    let response = client
+        .post(&login_url)
+        .header("X-XSRF-Token", csrf_token)
+        .form(&params)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Login failure");
+    }
+
+    // Query
+    let request_url = format!(
+        "{server}/api/overview/team/{team}",
+        server = server.url,
+        team = team
+    );
+    let response = client.get(&request_url).send().await?.error_for_status()?;
+    let mut page_list: Vec<Page> = response.json().await?;
+
+    let summary = sync_contents(&mut page_list, previous, |id| {
+        download_markdown(&client, server, id)
+    })
+    .await;
+
+    Ok((page_list, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use super::*;
+    use crate::fetcher::{mock, Flavor};
+
+    const TOKEN: &str = "secret-token";
+
+    async fn download(Path(id): Path<String>) -> Result<&'static str, StatusCode> {
+        match id.as_str() {
+            "Hk3kNJrGq" => Ok(include_str!("fixtures/meeting_notes.md")),
+            _ => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn login(headers: HeaderMap) -> StatusCode {
+        match headers.get("X-XSRF-Token") {
+            Some(token) if token == "Zm9vYmFyYmF6" => StatusCode::OK,
+            _ => StatusCode::FORBIDDEN,
+        }
+    }
+
+    async fn api_note(
+        headers: HeaderMap,
+        Path(id): Path<String>,
+    ) -> Result<&'static str, StatusCode> {
+        if headers.get(AUTHORIZATION).unwrap() != &format!("Bearer {}", TOKEN) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        match id.as_str() {
+            "Hk3kNJrGq" => Ok(include_str!("fixtures/hackmd_api_note.json")),
+            _ => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn api_notes(headers: HeaderMap) -> Result<&'static str, StatusCode> {
+        if headers.get(AUTHORIZATION).unwrap() != &format!("Bearer {}", TOKEN) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(include_str!("fixtures/hackmd_api_notes.json"))
+    }
+
+    #[tokio::test]
+    async fn login_downloads_team_notes() {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { include_str!("fixtures/hackmd_index.html") }),
+            )
+            .route("/login", post(login))
+            .route(
+                "/api/overview/team/my-team",
+                get(|| async { include_str!("fixtures/hackmd_overview.json") }),
+            )
+            .route("/:id/download", get(download));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hackmd);
+
+        let (page_list, summary) =
+            build_database_from_login(&server, "my-team", "user@example.com", "password", &[])
+                .await
+                .unwrap();
+
+        assert_eq!(summary.added, 2);
+        assert_eq!(page_list.len(), 2);
+        assert_eq!(page_list[0].id, "Hk3kNJrGq");
+        assert_eq!(page_list[0].lastchange_at, "2022-03-01T10:00:00.000Z");
+        assert_eq!(
+            page_list[0].content.as_deref(),
+            Some(include_str!("fixtures/meeting_notes.md"))
+        );
+        assert!(page_list[0].error.is_none());
+
+        assert_eq!(page_list[1].id, "rJx9bO8Mc");
+        assert!(page_list[1].content.is_none());
+        assert_eq!(page_list[1].error.as_ref().unwrap().status, Some(404));
+    }
+
+    #[tokio::test]
+    async fn failed_download_keeps_previous_content() {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { include_str!("fixtures/hackmd_index.html") }),
+            )
+            .route("/login", post(login))
+            .route(
+                "/api/overview/team/my-team",
+                get(|| async { include_str!("fixtures/hackmd_overview.json") }),
+            )
+            .route("/:id/download", get(download));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hackmd);
+        let previous = vec![Page {
+            id: "rJx9bO8Mc".to_string(),
+            title: "Broken note".to_string(),
+            lastchange_at: "2022-04-01T08:30:00.000Z".to_string(),
+            content: Some("Previous content".to_string()),
+            ..Page::default()
+        }];
+
+        let (page_list, summary) = build_database_from_login(
+            &server,
+            "my-team",
+            "user@example.com",
+            "password",
+            &previous,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((summary.added, summary.changed), (1, 1));
+        assert_eq!(page_list[1].content.as_deref(), Some("Previous content"));
+        assert_eq!(page_list[1].lastchange_at, "2022-04-01T08:30:00.000Z");
+        assert_eq!(page_list[1].error.as_ref().unwrap().status, Some(404));
+    }
+
+    #[tokio::test]
+    async fn login_failure() {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { include_str!("fixtures/hackmd_index.html") }),
+            )
+            .route("/login", post(|| async { StatusCode::UNAUTHORIZED }));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hackmd);
+
+        let result =
+            build_database_from_login(&server, "my-team", "user@example.com", "password", &[])
+                .await;
+
+        assert_eq!(result.unwrap_err().to_string(), "Login failure");
+    }
+
+    #[tokio::test]
+    async fn token_downloads_team_notes() {
+        let router = Router::new()
+            .route("/api/v1/teams/my-team/notes", get(api_notes))
+            .route("/api/v1/notes/:id", get(api_note));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hackmd);
+
+        let (page_list, summary) = build_database_from_api(&server, "my-team", TOKEN, &[])
+            .await
+            .unwrap();
+
+        assert_eq!(summary.added, 2);
+        assert_eq!(page_list[0].id, "Hk3kNJrGq");
+        assert_eq!(page_list[0].title, "Meeting notes");
+        assert_eq!(page_list[0].tags, vec!["meeting"]);
+        assert_eq!(page_list[0].lastchange_at, "2022-03-01T10:00:00+00:00");
+        assert_eq!(
+            page_list[0].content.as_deref(),
+            Some("# Meeting notes\n\n###### tags: `meeting`\n\n- Kubernetes upgrade\n")
+        );
+
+        // Notes that never changed only have a creation time.
+        assert_eq!(page_list[1].lastchange_at, "2022-04-12T08:30:00+00:00");
+        assert!(page_list[1].content.is_none());
+        assert_eq!(page_list[1].error.as_ref().unwrap().status, Some(404));
+    }
+}
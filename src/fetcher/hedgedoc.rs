@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use futures::{stream, StreamExt};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::{
+    build_client, download_markdown, store_download, sync_contents, Server, CONCURRENT_REQUESTS,
+};
+use crate::sync::SyncSummary;
+use crate::Page;
+
+/// Response of `/history`.
+#[derive(Deserialize, Debug)]
+struct History {
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryEntry {
+    id: String,
+    text: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Response of `/{id}/info`.
+#[derive(Deserialize, Debug)]
+struct NoteInfo {
+    title: String,
+    updatetime: String,
+}
+
+/// Download the notes listed in the history of the user.
+///
+/// CodiMD and HedgeDoc do not have teams, so the history is the closest
+/// equivalent to the team overview of HackMD.
+pub async fn build_database(
+    server: &Server,
+    user: &str,
+    password: &str,
+    previous: &[Page],
+) -> anyhow::Result<(Vec<Page>, SyncSummary)> {
+    let client = build_client(HeaderMap::new())?;
+
+    // Login
+    // See: https://docs.hedgedoc.org/dev/api/
+    let login_url = format!("{server}/login", server = server.url);
+    let mut params = HashMap::new();
+
+    params.insert("email", user);
+    params.insert("password", password);
+
+    let response = client.post(&login_url).form(&params).send().await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Login failure");
+    }
+
+    // Query
+    let history_url = format!("{server}/history", server = server.url);
+    let response = client.get(&history_url).send().await?.error_for_status()?;
+    let history: History = response.json().await?;
+
+    // The history only stores the time of the last visit, so the time of the
+    // last change has to be retrieved from the note metadata.
+    let infos = stream::iter(&history.history)
+        .map(|entry| {
+            let client = &client;
+            let info_url = format!("{server}/{id}/info", server = server.url, id = entry.id);
+
+            async move {
+                let response = client.get(&info_url).send().await?.error_for_status()?;
+                response
+                    .json::<NoteInfo>()
+                    .await
+                    .map_err(anyhow::Error::new)
+            }
+        })
+        .buffered(CONCURRENT_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+
+    // Without its time of last change, a note could not be carried over: a
+    // note whose info is missing is handled like a failed download.
+    let previous_pages: HashMap<&str, &Page> = previous
+        .iter()
+        .map(|page| (page.id.as_str(), page))
+        .collect();
+    let mut page_list: Vec<Page> = history
+        .history
+        .into_iter()
+        .zip(infos)
+        .map(|(entry, info)| match info {
+            Ok(info) => Page {
+                id: entry.id,
+                title: info.title,
+                lastchange_at: info.updatetime,
+                tags: entry.tags,
+                ..Page::default()
+            },
+            Err(err) => {
+                warn!("Unable to get the info of note {}: {:#}", entry.id, err);
+                let mut page = Page {
+                    id: entry.id,
+                    title: entry.text,
+                    tags: entry.tags,
+                    ..Page::default()
+                };
+                let old = previous_pages.get(page.id.as_str()).copied();
+                store_download(&mut page, old, Err(err));
+                page
+            }
+        })
+        .collect();
+
+    let summary = sync_contents(&mut page_list, previous, |id| {
+        download_markdown(&client, server, id)
+    })
+    .await;
+
+    Ok((page_list, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use super::*;
+    use crate::fetcher::{mock, Flavor};
+
+    async fn info(Path(id): Path<String>) -> Result<&'static str, StatusCode> {
+        match id.as_str() {
+            "features" => Ok(include_str!("fixtures/hedgedoc_info.json")),
+            _ => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn download(Path(id): Path<String>) -> Result<&'static str, StatusCode> {
+        match id.as_str() {
+            "features" => Ok(include_str!("fixtures/features.md")),
+            _ => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .route(
+                "/history",
+                get(|| async { include_str!("fixtures/hedgedoc_history.json") }),
+            )
+            .route("/:id/info", get(info))
+            .route("/:id/download", get(download))
+    }
+
+    #[tokio::test]
+    async fn downloads_history_notes() {
+        let router = router().route("/login", post(|| async { StatusCode::OK }));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hedgedoc);
+
+        let (page_list, summary) = build_database(&server, "user@example.com", "password", &[])
+            .await
+            .unwrap();
+
+        assert_eq!(summary.added, 2);
+        assert_eq!(page_list.len(), 2);
+        assert_eq!(page_list[0].id, "features");
+        assert_eq!(page_list[0].title, "Features");
+        assert_eq!(page_list[0].lastchange_at, "2022-05-08T14:00:00.000Z");
+        assert_eq!(page_list[0].tags, vec!["docs"]);
+        assert_eq!(
+            page_list[0].content.as_deref(),
+            Some(include_str!("fixtures/features.md"))
+        );
+
+        // Without info, the title of the history is used.
+        assert_eq!(page_list[1].id, "deleted-note");
+        assert_eq!(page_list[1].title, "Deleted note");
+        assert!(page_list[1].content.is_none());
+        assert_eq!(page_list[1].error.as_ref().unwrap().status, Some(404));
+    }
+
+    #[tokio::test]
+    async fn failed_info_keeps_previous_content() {
+        let router = Router::new()
+            .route(
+                "/history",
+                get(|| async { include_str!("fixtures/hedgedoc_history.json") }),
+            )
+            .route(
+                "/:id/info",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route("/:id/download", get(download))
+            .route("/login", post(|| async { StatusCode::OK }));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hedgedoc);
+        let previous = vec![Page {
+            id: "features".to_string(),
+            title: "Features".to_string(),
+            lastchange_at: "2022-05-01T08:30:00.000Z".to_string(),
+            content: Some("Previous content".to_string()),
+            ..Page::default()
+        }];
+
+        let (page_list, _) = build_database(&server, "user@example.com", "password", &previous)
+            .await
+            .unwrap();
+
+        // The note is not downloaded again, and the error is recorded.
+        assert_eq!(page_list[0].content.as_deref(), Some("Previous content"));
+        assert_eq!(page_list[0].lastchange_at, "2022-05-01T08:30:00.000Z");
+        assert_eq!(page_list[0].error.as_ref().unwrap().status, Some(500));
+        assert!(page_list[1].content.is_none());
+        assert_eq!(page_list[1].error.as_ref().unwrap().status, Some(500));
+    }
+
+    #[tokio::test]
+    async fn login_failure() {
+        let router = router().route("/login", post(|| async { StatusCode::UNAUTHORIZED }));
+        let server = Server::new(&mock::serve(router).await, None, Flavor::Hedgedoc);
+
+        let result = build_database(&server, "user@example.com", "password", &[]).await;
+
+        assert_eq!(result.unwrap_err().to_string(), "Login failure");
+    }
+}
@@ -0,0 +1,17 @@
+//! Local server replaying recorded responses, to test the fetchers and the
+//! Meilisearch client.
+
+use std::net::TcpListener;
+
+use axum::Router;
+
+/// Serve the routes on a random local port, and return the URL of the server.
+pub async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+    url
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use futures::{stream, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest_middleware::ClientWithMiddleware;
+use tracing::debug;
+
+use crate::credentials::Secret;
+use crate::sync::{self, SyncSummary};
+use crate::{DownloadError, Page};
+
+mod hackmd;
+mod hedgedoc;
+#[cfg(test)]
+pub mod mock;
+
+pub const DEFAULT_SERVER_URL: &str = "https://hackmd.io";
+
+const CONCURRENT_REQUESTS: usize = 5;
+
+/// Kind of server hosting the notes.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flavor {
+    /// hackmd.io or a self-hosted HackMD EE instance.
+    Hackmd,
+    /// CodiMD or HedgeDoc instance.
+    Hedgedoc,
+}
+
+/// How to authenticate against the server.
+#[derive(Debug)]
+pub enum Auth {
+    /// API token, used with the official HackMD REST API.
+    Token(Secret),
+    /// E-mail and password, used with the login form of the website.
+    Login { user: String, password: Secret },
+}
+
+/// Server to retrieve the notes from.
+#[derive(Debug)]
+pub struct Server {
+    /// Base URL of the website, without trailing slash.
+    pub url: String,
+    /// Base URL of the REST API, if it differs from the default one.
+    pub api_url: Option<String>,
+    pub flavor: Flavor,
+}
+
+impl Server {
+    pub fn new(url: &str, api_url: Option<&str>, flavor: Flavor) -> Self {
+        Server {
+            url: url.trim_end_matches('/').to_string(),
+            api_url: api_url.map(|url| url.trim_end_matches('/').to_string()),
+            flavor,
+        }
+    }
+
+    /// Download the notes of a team (HackMD) or of the user history (HedgeDoc).
+    ///
+    /// Only the notes that are new or changed since the `previous` database
+    /// are downloaded, the content of the other ones is carried over.
+    pub async fn build_database(
+        &self,
+        team: Option<&str>,
+        auth: &Auth,
+        previous: &[Page],
+    ) -> anyhow::Result<(Vec<Page>, SyncSummary)> {
+        match self.flavor {
+            Flavor::Hackmd => {
+                let team = team.ok_or_else(|| crate::UserInputError::MissingArgument {
+                    arg: "team".to_string(),
+                })?;
+                hackmd::build_database(self, team, auth, previous).await
+            }
+            Flavor::Hedgedoc => match auth {
+                Auth::Token(_) => {
+                    anyhow::bail!("API tokens are not supported by HedgeDoc servers")
+                }
+                Auth::Login { user, password } => {
+                    hedgedoc::build_database(self, user, password.expose(), previous).await
+                }
+            },
+        }
+    }
+}
+
+fn build_client(headers: HeaderMap) -> anyhow::Result<ClientWithMiddleware> {
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .default_headers(headers)
+        .tcp_keepalive(Duration::new(60, 0))
+        .build()?;
+
+    // Retry up to 3 times with increasing intervals between attempts.
+    let retry_policy =
+        reqwest_retry::policies::ExponentialBackoff::builder().build_with_max_retries(3);
+
+    Ok(reqwest_middleware::ClientBuilder::new(client)
+        .with(reqwest_retry::RetryTransientMiddleware::new_with_policy(
+            retry_policy,
+        ))
+        .build())
+}
+
+/// Describe why the download of a note failed.
+fn download_error(err: &anyhow::Error) -> DownloadError {
+    let status = err.chain().find_map(|cause| {
+        if let Some(reqwest_middleware::Error::Reqwest(err)) = cause.downcast_ref() {
+            err.status()
+        } else {
+            cause.downcast_ref::<reqwest::Error>()?.status()
+        }
+    });
+
+    DownloadError {
+        status: status.map(|status| status.as_u16()),
+        message: format!("{:#}", err),
+    }
+}
+
+/// Store the downloaded content of a page, or the reason why it failed.
+///
+/// When the download failed, the content of the note in the `previous`
+/// database is kept, along with its time of last change, so that its search
+/// documents stay as they were until the next update downloads it again.
+fn store_download(
+    page: &mut Page,
+    previous: Option<&Page>,
+    result: anyhow::Result<Option<String>>,
+) {
+    match result {
+        Ok(content) => {
+            page.content = content;
+            page.error = None;
+        }
+        Err(err) => {
+            if let Some(previous) = previous.filter(|previous| previous.content.is_some()) {
+                page.content = previous.content.clone();
+                page.lastchange_at = previous.lastchange_at.clone();
+            }
+            page.error = Some(download_error(&err));
+        }
+    }
+}
+
+/// Store the results of the downloads of the `pending` pages.
+fn store_downloads(
+    page_list: &mut [Page],
+    pending: &[usize],
+    results: Vec<anyhow::Result<Option<String>>>,
+    previous: &[Page],
+) {
+    let previous: HashMap<&str, &Page> = previous
+        .iter()
+        .map(|page| (page.id.as_str(), page))
+        .collect();
+    for (result, &idx) in results.into_iter().zip(pending) {
+        let page = &mut page_list[idx];
+        let old = previous.get(page.id.as_str()).copied();
+        store_download(page, old, result);
+    }
+}
+
+/// Indices of the pages whose content still has to be downloaded, skipping
+/// the ones that already failed.
+fn pending(page_list: &[Page]) -> Vec<usize> {
+    page_list
+        .iter()
+        .enumerate()
+        .filter(|(_, page)| page.content.is_none() && page.error.is_none())
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Carry over the unchanged notes of the previous database, then download
+/// the content of the other ones with `download`, given the id of each note.
+async fn sync_contents<F, Fut>(
+    page_list: &mut [Page],
+    previous: &[Page],
+    download: F,
+) -> SyncSummary
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<Option<String>>>,
+{
+    let summary = sync::carry_over(page_list, previous);
+    let pending = pending(page_list);
+
+    let bodies = stream::iter(&pending)
+        .map(|&idx| {
+            let id = page_list[idx].id.clone();
+            debug!("Downloading {}", id);
+            download(id)
+        })
+        .buffered(CONCURRENT_REQUESTS);
+
+    let results = bodies.collect::<Vec<_>>().await;
+    store_downloads(page_list, &pending, results, previous);
+
+    summary
+}
+
+/// Download the Markdown source of a note from `{server}/{id}/download`.
+async fn download_markdown(
+    client: &ClientWithMiddleware,
+    server: &Server,
+    id: String,
+) -> anyhow::Result<Option<String>> {
+    let page_url = format!("{server}/{id}/download", server = server.url, id = id);
+    let response = client.get(&page_url).send().await?.error_for_status()?;
+    Ok(Some(response.text().await?))
+}
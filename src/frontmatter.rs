@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::Page;
+
+/// Metadata found in a note.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
+    /// Other entries of the front matter.
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Parser of the YAML front matter and of the `###### tags:` lines.
+///
+/// See: https://hackmd.io/s/yaml-metadata
+pub struct Parser {
+    tags_line: Regex,
+    quoted_tag: Regex,
+}
+
+/// Split the YAML front matter, delimited by `---` lines, from the rest of the
+/// note.
+pub fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let content = content.trim_start_matches('\u{feff}');
+    let rest = content.strip_prefix("---")?;
+    let rest = rest
+        .strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let delimiter = line.trim_end();
+        if delimiter == "---" || delimiter == "..." {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            tags_line: Regex::new(r"(?m)^#{1,6}[ \t]*tags:[ \t]*(.*?)[ \t]*$").unwrap(),
+            quoted_tag: Regex::new(r"`([^`]+)`").unwrap(),
+        }
+    }
+
+    /// Tags of a `tags:` line, either quoted (`` `a` `b` ``) or separated by
+    /// commas.
+    fn tag_list(&self, list: &str) -> Vec<String> {
+        let quoted: Vec<String> = self
+            .quoted_tag
+            .captures_iter(list)
+            .map(|cap| cap[1].trim().to_string())
+            .collect();
+        if !quoted.is_empty() {
+            return quoted;
+        }
+
+        list.split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    pub fn parse(&self, content: &str) -> Metadata {
+        let mut metadata = Metadata::default();
+
+        if let Some((yaml, _)) = split_front_matter(content) {
+            match serde_yaml::from_str::<BTreeMap<String, serde_yaml::Value>>(yaml) {
+                Ok(entries) => {
+                    for (key, value) in entries {
+                        match (key.as_str(), value) {
+                            ("tags", serde_yaml::Value::String(list)) => {
+                                metadata.tags.extend(self.tag_list(&list))
+                            }
+                            ("tags", serde_yaml::Value::Sequence(list)) => metadata.tags.extend(
+                                list.iter()
+                                    .filter_map(|tag| tag.as_str())
+                                    .map(|tag| tag.trim().to_string()),
+                            ),
+                            ("description", serde_yaml::Value::String(description)) => {
+                                metadata.description = Some(description)
+                            }
+                            ("lang", serde_yaml::Value::String(lang)) => metadata.lang = Some(lang),
+                            // The title is already given by the server.
+                            ("title", _) => {}
+                            (_, value) => {
+                                if let Ok(value) = serde_json::to_value(value) {
+                                    metadata.extra.insert(key, value);
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => debug!("Invalid front matter: {}", e),
+            }
+        }
+
+        for cap in self.tags_line.captures_iter(content) {
+            metadata.tags.extend(self.tag_list(&cap[1]));
+        }
+
+        metadata
+    }
+}
+
+/// Fill the metadata fields of the pages from their content.
+pub fn fill_metadata(page_list: &mut [Page]) {
+    let parser = Parser::new();
+
+    for page in page_list.iter_mut() {
+        let content = match &page.content {
+            Some(content) => content,
+            None => continue,
+        };
+        let metadata = parser.parse(content);
+
+        for tag in metadata.tags {
+            if !tag.is_empty() && !page.tags.contains(&tag) {
+                page.tags.push(tag);
+            }
+        }
+        page.description = metadata.description.or_else(|| page.description.take());
+        page.lang = metadata.lang.or_else(|| page.lang.take());
+        page.metadata = metadata.extra;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter() {
+        let content = "---\ntitle: Ignored\ntags: meeting, ops\ndescription: Weekly meeting\nlang: fr\nbreaks: false\n---\n# Meeting\n";
+
+        let metadata = Parser::new().parse(content);
+
+        assert_eq!(metadata.tags, vec!["meeting", "ops"]);
+        assert_eq!(metadata.description.as_deref(), Some("Weekly meeting"));
+        assert_eq!(metadata.lang.as_deref(), Some("fr"));
+        assert_eq!(metadata.extra.len(), 1);
+        assert_eq!(metadata.extra["breaks"], Value::Bool(false));
+    }
+
+    #[test]
+    fn front_matter_tag_list() {
+        let content = "---\ntags:\n  - meeting\n  - ' ops '\n---\n";
+
+        let metadata = Parser::new().parse(content);
+
+        assert_eq!(metadata.tags, vec!["meeting", "ops"]);
+    }
+
+    #[test]
+    fn tags_lines() {
+        let content =
+            "# Notes\n\n###### tags: `meeting` `weekly ops`\n\n## Other\n#### tags: a, b\n";
+
+        let metadata = Parser::new().parse(content);
+
+        assert_eq!(metadata.tags, vec!["meeting", "weekly ops", "a", "b"]);
+    }
+
+    #[test]
+    fn invalid_front_matter() {
+        let content = "---\ntags: [unclosed\n---\n###### tags: `meeting`\n";
+
+        let metadata = Parser::new().parse(content);
+
+        assert_eq!(metadata.tags, vec!["meeting"]);
+        assert!(metadata.extra.is_empty());
+    }
+
+    #[test]
+    fn front_matter_delimiters() {
+        assert_eq!(split_front_matter("---\ntags: meeting\n# Title\n"), None);
+        assert_eq!(
+            split_front_matter("\u{feff}---\r\ntags: meeting\r\n---\r\nBody"),
+            Some(("tags: meeting\r\n", "Body"))
+        );
+    }
+
+    #[test]
+    fn fill_metadata_merges_tags() {
+        let mut page_list = vec![Page {
+            id: "note".to_string(),
+            content: Some("---\ntags: meeting\n---\n###### tags: `ops`\n".to_string()),
+            tags: vec!["meeting".to_string(), "api".to_string()],
+            ..Page::default()
+        }];
+
+        fill_metadata(&mut page_list);
+
+        assert_eq!(page_list[0].tags, vec!["meeting", "api", "ops"]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{self, IsTerminal, Write};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::Page;
+
+/// Options of the `grep` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct GrepArgs {
+    /// Regular expression searched in the titles and contents of the notes.
+    pub pattern: String,
+
+    /// Search case-insensitively.
+    #[clap(short, long)]
+    pub ignore_case: bool,
+
+    /// Search the pattern as a literal string instead of a regular expression.
+    #[clap(short = 'F', long)]
+    pub fixed_strings: bool,
+
+    /// Number of lines shown before and after each matching line.
+    #[clap(short = 'C', long, default_value = "0")]
+    pub context: usize,
+}
+
+/// Colors of the output, empty when it is not a terminal.
+struct Colors {
+    heading: &'static str,
+    line_number: &'static str,
+    matched: &'static str,
+    reset: &'static str,
+}
+
+impl Colors {
+    fn new() -> Self {
+        if io::stdout().is_terminal() {
+            Colors {
+                heading: "\x1b[1;35m",
+                line_number: "\x1b[32m",
+                matched: "\x1b[1;31m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Colors {
+                heading: "",
+                line_number: "",
+                matched: "",
+                reset: "",
+            }
+        }
+    }
+
+    fn highlight(&self, re: &Regex, line: &str) -> String {
+        if self.matched.is_empty() {
+            return line.to_string();
+        }
+        re.replace_all(line, |cap: &regex::Captures| {
+            format!("{}{}{}", self.matched, &cap[0], self.reset)
+        })
+        .into_owned()
+    }
+}
+
+/// Print the matches of a note, ripgrep-style: the matching lines are
+/// numbered with a `:`, the context lines with a `-`, and non-contiguous
+/// groups of lines are separated by `--`.
+fn print_note(
+    out: &mut impl Write,
+    re: &Regex,
+    page: &Page,
+    server_url: &str,
+    context: usize,
+    colors: &Colors,
+) -> io::Result<bool> {
+    let lines: Vec<&str> = page
+        .content
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .collect();
+    let matches: Vec<usize> = (0..lines.len())
+        .filter(|&idx| re.is_match(lines[idx]))
+        .collect();
+    if matches.is_empty() && !re.is_match(&page.title) {
+        return Ok(false);
+    }
+
+    writeln!(
+        out,
+        "{}{}{}: {} ({}/{})",
+        colors.heading,
+        page.id,
+        colors.reset,
+        colors.highlight(re, &page.title),
+        server_url,
+        page.id
+    )?;
+
+    // Lines to show, as ranges merged with their neighbours.
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &idx in &matches {
+        let start = idx.saturating_sub(context);
+        let end = (idx + context).min(lines.len() - 1);
+        match groups.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => *last_end = end,
+            _ => groups.push((start, end)),
+        }
+    }
+
+    for (n, (start, end)) in groups.into_iter().enumerate() {
+        if n > 0 && context > 0 {
+            writeln!(out, "--")?;
+        }
+        for (idx, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+            let separator = if matches.binary_search(&idx).is_ok() {
+                ':'
+            } else {
+                '-'
+            };
+            writeln!(
+                out,
+                "{}{}{}{}{}",
+                colors.line_number,
+                idx + 1,
+                colors.reset,
+                separator,
+                colors.highlight(re, line)
+            )?;
+        }
+    }
+    writeln!(out)?;
+
+    Ok(true)
+}
+
+/// Search the titles and contents of the notes, and print the matches.
+/// Returns whether anything matched.
+pub fn run(page_list: &[Page], server_url: &str, args: &GrepArgs) -> anyhow::Result<bool> {
+    let pattern = if args.fixed_strings {
+        regex::escape(&args.pattern)
+    } else {
+        args.pattern.clone()
+    };
+    let re = RegexBuilder::new(&pattern)
+        .case_insensitive(args.ignore_case)
+        .build()?;
+
+    let colors = Colors::new();
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut found = false;
+
+    for page in page_list.iter().filter(|page| !page.deleted) {
+        match print_note(&mut out, &re, page, server_url, args.context, &colors) {
+            Ok(matched) => found |= matched,
+            // The reader of a pipeline went away (e.g. `head`).
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(found),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match out.flush() {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(found),
+    }
+}
@@ -0,0 +1,366 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::DateTime as ChronoDateTime;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, RegexQuery};
+use tantivy::schema::{DateOptions, Field, Schema, Value, FAST, STORED, STRING, TEXT};
+use tantivy::snippet::{Snippet, SnippetGenerator};
+use tantivy::{DateTime, DocAddress, Index, IndexWriter, Order, TantivyDocument};
+use tracing::{debug, info};
+
+use crate::search::{Hit, SearchArgs, SearchError, HIGHLIGHT_POST, HIGHLIGHT_PRE};
+use crate::sections;
+use crate::Page;
+
+/// Memory budget of the index writer, in bytes.
+const WRITER_MEMORY: usize = 50_000_000;
+
+/// Maximum length of the snippets, in characters.
+const SNIPPET_CHARS: usize = 200;
+
+/// Fields of the local index, named like the fields of the Meilisearch
+/// documents. Notes and sections share the same schema, the fields that do not
+/// apply being left empty.
+pub struct Fields {
+    pub id: Field,
+    pub note_id: Field,
+    pub title: Field,
+    pub breadcrumb: Field,
+    pub anchor: Field,
+    pub url: Field,
+    pub lastchange_at: Field,
+    /// Last change time as a date, to sort the results.
+    pub lastchange_time: Field,
+    pub tags: Field,
+    pub description: Field,
+    pub lang: Field,
+    pub text: Field,
+    pub content: Field,
+}
+
+impl Fields {
+    pub fn schema() -> Schema {
+        let mut builder = Schema::builder();
+        builder.add_text_field("id", STRING | STORED);
+        builder.add_text_field("noteId", STRING | STORED);
+        builder.add_text_field("title", TEXT | STORED);
+        builder.add_text_field("breadcrumb", TEXT | STORED);
+        builder.add_text_field("anchor", STORED);
+        builder.add_text_field("url", STORED);
+        builder.add_text_field("lastchangeAt", STRING | STORED);
+        builder.add_date_field(
+            "lastchangeTime",
+            DateOptions::default().set_indexed().set_fast(),
+        );
+        builder.add_text_field("tags", STRING | STORED | FAST);
+        builder.add_text_field("description", TEXT | STORED);
+        builder.add_text_field("lang", STRING | STORED | FAST);
+        builder.add_text_field("text", TEXT | STORED);
+        builder.add_text_field("content", STORED);
+        builder.build()
+    }
+
+    pub fn new(schema: &Schema) -> anyhow::Result<Fields> {
+        Ok(Fields {
+            id: schema.get_field("id")?,
+            note_id: schema.get_field("noteId")?,
+            title: schema.get_field("title")?,
+            breadcrumb: schema.get_field("breadcrumb")?,
+            anchor: schema.get_field("anchor")?,
+            url: schema.get_field("url")?,
+            lastchange_at: schema.get_field("lastchangeAt")?,
+            lastchange_time: schema.get_field("lastchangeTime")?,
+            tags: schema.get_field("tags")?,
+            description: schema.get_field("description")?,
+            lang: schema.get_field("lang")?,
+            text: schema.get_field("text")?,
+            content: schema.get_field("content")?,
+        })
+    }
+
+    fn add_lastchange(&self, document: &mut TantivyDocument, lastchange_at: &str) {
+        document.add_text(self.lastchange_at, lastchange_at);
+        if let Ok(time) = ChronoDateTime::parse_from_rfc3339(lastchange_at) {
+            document.add_date(
+                self.lastchange_time,
+                DateTime::from_timestamp_secs(time.timestamp()),
+            );
+        }
+    }
+
+    fn note(&self, page: &Page, server_url: &str) -> TantivyDocument {
+        let mut document = TantivyDocument::default();
+        document.add_text(self.id, &page.id);
+        document.add_text(self.note_id, &page.id);
+        document.add_text(self.title, &page.title);
+        document.add_text(self.url, format!("{}/{}", server_url, page.id));
+        self.add_lastchange(&mut document, &page.lastchange_at);
+        for tag in &page.tags {
+            document.add_text(self.tags, tag);
+        }
+        if let Some(description) = &page.description {
+            document.add_text(self.description, description);
+        }
+        if let Some(lang) = &page.lang {
+            document.add_text(self.lang, lang);
+        }
+        if let Some(text) = &page.text {
+            document.add_text(self.text, text);
+        }
+        if let Some(content) = &page.content {
+            document.add_text(self.content, content);
+        }
+        document
+    }
+
+    fn section(&self, section: &sections::SectionDocument) -> TantivyDocument {
+        let mut document = TantivyDocument::default();
+        document.add_text(self.id, &section.id);
+        document.add_text(self.note_id, section.note_id);
+        document.add_text(self.title, section.title);
+        for title in &section.breadcrumb {
+            document.add_text(self.breadcrumb, title);
+        }
+        document.add_text(self.anchor, &section.anchor);
+        document.add_text(self.url, &section.url);
+        self.add_lastchange(&mut document, section.lastchange_at);
+        for tag in section.tags {
+            document.add_text(self.tags, tag);
+        }
+        if let Some(lang) = section.lang {
+            document.add_text(self.lang, lang);
+        }
+        document.add_text(self.text, &section.text);
+        document
+    }
+}
+
+/// Open the local index in a directory, creating it if needed.
+fn open_or_create(dir: &Path) -> anyhow::Result<Index> {
+    fs::create_dir_all(dir)?;
+    let directory = MmapDirectory::open(dir)?;
+    Index::open_or_create(directory, Fields::schema())
+        .map_err(|e| anyhow::anyhow!("unable to open the local index in {}: {}", dir.display(), e))
+}
+
+/// Open an existing local index.
+pub fn open(dir: &Path) -> anyhow::Result<Index> {
+    Index::open_in_dir(dir)
+        .map_err(|e| anyhow::anyhow!("unable to open the local index in {}: {}", dir.display(), e))
+}
+
+/// Add the pages, or their sections, to the index, and return the number of
+/// documents.
+fn add_pages(
+    writer: &IndexWriter,
+    fields: &Fields,
+    page_list: &[Page],
+    server_url: &str,
+    sections: bool,
+) -> anyhow::Result<usize> {
+    if sections {
+        let documents = sections::split_pages(page_list, server_url);
+        for section in &documents {
+            writer.add_document(fields.section(section))?;
+        }
+        Ok(documents.len())
+    } else {
+        let mut count = 0;
+        for page in page_list.iter().filter(|page| !page.deleted) {
+            writer.add_document(fields.note(page, server_url))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Replace the content of the local index with the pages, or with their
+/// sections.
+///
+/// The previous documents are only dropped when the new ones are committed, so
+/// searches keep working on the old index until then, and deleted notes do not
+/// need to be tracked.
+pub fn build(
+    page_list: &[Page],
+    dir: &Path,
+    server_url: &str,
+    sections: bool,
+) -> anyhow::Result<()> {
+    let index = open_or_create(dir)?;
+    let fields = Fields::new(&index.schema())?;
+    let mut writer: IndexWriter = index.writer(WRITER_MEMORY)?;
+
+    writer.delete_all_documents()?;
+    let count = add_pages(&writer, &fields, page_list, server_url, sections)?;
+
+    info!("Indexing {} documents in {}", count, dir.display());
+    writer.commit()?;
+    writer.wait_merging_threads()?;
+
+    Ok(())
+}
+
+/// Index the pages in memory, to search the database without any index.
+pub fn in_memory(page_list: &[Page], server_url: &str, sections: bool) -> anyhow::Result<Index> {
+    let index = Index::create_in_ram(Fields::schema());
+    let fields = Fields::new(&index.schema())?;
+    let mut writer: IndexWriter = index.writer(WRITER_MEMORY)?;
+
+    add_pages(&writer, &fields, page_list, server_url, sections)?;
+    writer.commit()?;
+    writer.wait_merging_threads()?;
+
+    Ok(index)
+}
+
+/// Order of the results for a `--sort` option: only the last change time is
+/// available.
+fn sort_order(sort: &[String]) -> Result<Option<Order>, SearchError> {
+    match sort {
+        [] => Ok(None),
+        [sort] if sort == "lastchangeAt" || sort == "lastchangeAt:asc" => Ok(Some(Order::Asc)),
+        [sort] if sort == "lastchangeAt:desc" => Ok(Some(Order::Desc)),
+        sort => Err(SearchError::UnsupportedSort {
+            backend: "The local index",
+            sort: sort.join(", "),
+        }),
+    }
+}
+
+/// Text of the snippet, with the matches highlighted the same way as with
+/// Meilisearch.
+fn highlight(snippet: &Snippet) -> String {
+    let fragment = snippet.fragment();
+    let mut text = String::new();
+    let mut start = 0;
+    for range in snippet.highlighted() {
+        text.push_str(&fragment[start..range.start]);
+        text.push_str(HIGHLIGHT_PRE);
+        text.push_str(&fragment[range.clone()]);
+        text.push_str(HIGHLIGHT_POST);
+        start = range.end;
+    }
+    text.push_str(&fragment[start..]);
+    text
+}
+
+/// Search the index, with the same searchable attributes as the default
+/// Meilisearch profile, the title first.
+pub fn search(index: &Index, args: &SearchArgs) -> anyhow::Result<Vec<Hit>> {
+    let fields = Fields::new(&index.schema())?;
+    let searcher = index.reader()?.searcher();
+
+    let mut parser = QueryParser::for_index(
+        index,
+        vec![
+            fields.title,
+            fields.breadcrumb,
+            fields.tags,
+            fields.description,
+            fields.text,
+        ],
+    );
+    parser.set_field_boost(fields.title, 2.0);
+    let text = args.text();
+    let (mut query, errors) = parser.parse_query_lenient(&text);
+    for e in errors {
+        debug!("Ignoring part of the query: {}", e);
+    }
+    // When searching as you type, the last word also matches the words it
+    // starts like with Meilisearch, unless it is followed by a space.
+    let last_word = if !args.prefix || text.ends_with(char::is_whitespace) {
+        None
+    } else {
+        text.split_whitespace()
+            .last()
+            .filter(|word| word.chars().all(char::is_alphanumeric))
+    };
+    if let Some(word) = last_word {
+        let pattern = format!("{}.*", word.to_lowercase());
+        let mut clauses = vec![(Occur::Should, query)];
+        for field in [
+            fields.title,
+            fields.breadcrumb,
+            fields.description,
+            fields.text,
+        ] {
+            let prefix: Box<dyn Query> = Box::new(RegexQuery::from_pattern(&pattern, field)?);
+            clauses.push((Occur::Should, prefix));
+        }
+        query = Box::new(BooleanQuery::new(clauses));
+    }
+    if let Some(filter) = &args.filter {
+        let filter = parser
+            .parse_query(filter)
+            .map_err(|e| SearchError::InvalidFilter {
+                filter: filter.clone(),
+                message: e.to_string(),
+            })?;
+        query = Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query),
+            (Occur::Must, filter),
+        ]));
+    }
+
+    let top_docs = TopDocs::with_limit(args.limit.max(1)).and_offset(args.offset);
+    let (total, addresses): (usize, Vec<DocAddress>) = match sort_order(&args.sort)? {
+        Some(order) => {
+            let (total, docs) = searcher.search(
+                &query,
+                &(
+                    Count,
+                    top_docs.order_by_fast_field::<DateTime>("lastchangeTime", order),
+                ),
+            )?;
+            (
+                total,
+                docs.into_iter().map(|(_, address)| address).collect(),
+            )
+        }
+        None => {
+            let (total, docs) = searcher.search(&query, &(Count, top_docs))?;
+            (
+                total,
+                docs.into_iter().map(|(_, address)| address).collect(),
+            )
+        }
+    };
+    info!("Found {} results", total);
+
+    let mut snippets = SnippetGenerator::create(&searcher, &*query, fields.text)?;
+    snippets.set_max_num_chars(SNIPPET_CHARS);
+
+    let mut hits = Vec::new();
+    for address in addresses {
+        let document: TantivyDocument = searcher.doc(address)?;
+        let get = |field| {
+            document
+                .get_first(field)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let snippet = snippets.snippet_from_doc(&document);
+        let snippet = if snippet.is_empty() {
+            get(fields.text).chars().take(SNIPPET_CHARS).collect()
+        } else {
+            highlight(&snippet)
+        };
+
+        hits.push(Hit {
+            title: get(fields.title),
+            snippet,
+            lastchange_at: get(fields.lastchange_at),
+            url: get(fields.url),
+            content: match get(fields.content) {
+                content if content.is_empty() => get(fields.text),
+                content => content,
+            },
+        });
+    }
+
+    Ok(hits)
+}
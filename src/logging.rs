@@ -0,0 +1,48 @@
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+/// Format of the log output.
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+/// Log level for the given number of `-v` and `-q` flags, `info` by default.
+fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    match i16::from(verbose) - i16::from(quiet) {
+        i16::MIN..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Set up the global logger. Logs are written to stderr.
+///
+/// Secrets must never be logged as is: credentials are wrapped in
+/// [`crate::credentials::Secret`], which is redacted when formatted.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat) {
+    // The local index library is chatty: its own messages are shown one
+    // level of verbosity later.
+    let targets = Targets::new()
+        .with_default(level(verbose, quiet))
+        .with_target("tantivy", level(verbose, quiet.saturating_add(1)));
+    let layer = fmt::layer().with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => tracing_subscriber::registry()
+            .with(layer.with_target(false))
+            .with(targets)
+            .init(),
+        LogFormat::Json => tracing_subscriber::registry()
+            .with(layer.json())
+            .with(targets)
+            .init(),
+    }
+}
@@ -1,19 +1,37 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Write;
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Mutex;
 use std::time::Duration;
 
-use clap::Parser;
-use futures::{stream, StreamExt};
-use regex::Regex;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 
 use thiserror::Error;
-
-const SERVER_URL: &str = "https://hackmd.io";
+use tracing::{info, warn};
+
+mod archive;
+mod config;
+mod credentials;
+mod export;
+mod fetcher;
+mod frontmatter;
+mod grep;
+mod local_index;
+mod logging;
+mod markdown;
+mod meilisearch;
+mod search;
+mod sections;
+mod serve;
+mod storage;
+mod sync;
+mod tui;
+
+use config::Config;
+use fetcher::{Auth, Flavor, Server};
+use logging::LogFormat;
+use storage::{Database, Header};
 
 #[derive(Error, Debug)]
 pub enum UserInputError {
@@ -28,149 +46,299 @@ struct Args {
     #[clap(short, long)]
     team: Option<String>,
 
-    /// Path to the output JSON database.
-    #[clap(short, long, default_value = "hackmd.json")]
+    /// Path to the output database: a SQLite database if it ends with .db,
+    /// .sqlite or .sqlite3, a JSON file otherwise. When searching, "-" reads a
+    /// JSON database from stdin.
+    #[clap(global = true, short, long, default_value = "hackmd.json")]
     database: String,
 
     /// Whether to update the database.
     #[clap(short, long)]
     update: bool,
 
+    /// Download every note again when updating, instead of only the new and
+    /// changed ones.
+    #[clap(long)]
+    full: bool,
+
+    /// Keep the notes deleted from the server in the database, flagged as
+    /// deleted, instead of dropping them. They are removed from Meilisearch
+    /// either way.
+    #[clap(long)]
+    keep_deleted: bool,
+
+    /// Exit with an error if more notes than this could not be downloaded.
+    #[clap(long)]
+    max_failures: Option<usize>,
+
     /// Meilisearch URL.
-    #[clap(short, long)]
+    #[clap(global = true, short, long)]
     meilisearch: Option<String>,
+
+    /// Directory of a local full-text index, to search without a Meilisearch
+    /// server. It is rebuilt from the database on every run.
+    #[clap(global = true, long)]
+    index_dir: Option<String>,
+
+    /// Git repository mirroring the notes as Markdown files, created if
+    /// needed. Each run commits the notes that were added, changed and
+    /// deleted.
+    #[clap(long)]
+    archive: Option<String>,
+
+    /// Meilisearch API key (master key or admin key).
+    #[clap(
+        long,
+        global = true,
+        env = "MEILISEARCH_KEY",
+        hide_env_values = true,
+        default_value = ""
+    )]
+    meilisearch_key: String,
+
+    /// Name of the Meilisearch index.
+    #[clap(global = true, long, default_value = "pages")]
+    index: String,
+
+    /// Primary key of the Meilisearch index: the attribute holding the
+    /// identifiers of the documents.
+    #[clap(global = true, long, default_value = "id")]
+    primary_key: String,
+
+    /// Rebuild the Meilisearch index from scratch in a temporary index, then
+    /// swap it with the live one (needs Meilisearch 1.0 or later).
+    #[clap(long)]
+    rebuild: bool,
+
+    /// Index one document per heading section instead of one per note.
+    #[clap(global = true, long)]
+    sections: bool,
+
+    /// Do not wait for Meilisearch to process the indexing tasks.
+    #[clap(long, conflicts_with = "rebuild")]
+    no_wait: bool,
+
+    /// Maximum number of documents sent to Meilisearch at once.
+    #[clap(long, default_value = "1000")]
+    batch_size: usize,
+
+    /// Maximum size of the documents sent to Meilisearch at once, in bytes.
+    #[clap(long, default_value = "10000000")]
+    batch_max_bytes: usize,
+
+    /// Number of batches of documents sent to Meilisearch at the same time.
+    #[clap(long, default_value = "2")]
+    upload_concurrency: usize,
+
+    /// Maximum time to wait for each Meilisearch task, in seconds.
+    #[clap(long, conflicts_with = "no-wait")]
+    task_timeout: Option<u64>,
+
+    /// HackMD API token. If none is given, log in with e-mail and password.
+    /// Can also be given with the HACKMD_TOKEN environment variable.
+    #[clap(long)]
+    token: Option<String>,
+
+    /// Path to a file containing the HackMD API token.
+    #[clap(long, conflicts_with = "token")]
+    token_file: Option<String>,
+
+    /// E-mail used to log in, if no API token is given.
+    #[clap(long, env = "HACKMD_USER")]
+    user: Option<String>,
+
+    /// Path to a file containing the password used to log in. Can also be
+    /// given with the HACKMD_PASSWORD environment variable.
+    #[clap(long)]
+    password_file: Option<String>,
+
+    /// Look up the API token and password in the OS keyring, under the
+    /// "hackmd-search" service.
+    #[clap(long)]
+    keyring: bool,
+
+    /// URL of the HackMD, CodiMD or HedgeDoc server.
+    #[clap(global = true, long, default_value = fetcher::DEFAULT_SERVER_URL)]
+    server: String,
+
+    /// Kind of server given with --server.
+    #[clap(long, arg_enum, default_value = "hackmd")]
+    flavor: Flavor,
+
+    /// URL of the HackMD REST API, if it is not served under the default
+    /// location (https://api.hackmd.io/v1 for hackmd.io, <SERVER>/api/v1
+    /// otherwise).
+    #[clap(long)]
+    api_url: Option<String>,
+
+    /// Increase the verbosity (-v for debug, -vv for trace).
+    #[clap(global = true, short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    /// Decrease the verbosity (-q for warnings only, -qq for errors only).
+    #[clap(
+        global = true,
+        short,
+        long,
+        parse(from_occurrences),
+        conflicts_with = "verbose"
+    )]
+    quiet: u8,
+
+    /// Format of the logs, written to stderr.
+    #[clap(global = true, long, arg_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Path to a TOML configuration file.
+    #[clap(global = true, short, long)]
+    config: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Search the notes with Meilisearch if --meilisearch is given, in the
+    /// local index if --index-dir is given, or else in the database.
+    Search(search::SearchArgs),
+    /// Search the titles and contents of the notes in the database with a
+    /// regular expression, like grep, without any index. The process exits
+    /// with status 1 if nothing matched.
+    Grep(grep::GrepArgs),
+    /// Search the notes interactively, with the same backends as the search
+    /// subcommand.
+    Tui(tui::TuiArgs),
+    /// Serve a search page and a JSON search API (/api/search) over HTTP,
+    /// with the same backends as the search subcommand.
+    Serve(serve::ServeArgs),
+    /// Export the notes of the database as Markdown files, with their id,
+    /// title, last change time and URL in their front matter. Exporting again
+    /// to the same directory only updates the files that changed.
+    Export(export::ExportArgs),
+    /// Import a JSON database into the database given with --database, e.g.
+    /// to move to SQLite.
+    Import {
+        /// Path to the JSON database to import.
+        path: String,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("{failed} notes could not be downloaded (maximum: {max})")]
+    TooManyFailures { failed: usize, max: usize },
+}
+
+/// Reason why the download of a note failed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadError {
+    /// HTTP status code, if the server answered.
+    status: Option<u16>,
+    message: String,
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "HTTP {}: {}", status, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
-struct Page {
+pub struct Page {
     id: String,
     title: String,
     lastchange_at: String,
     content: Option<String>,
+    /// Plain text rendering of the content, without Markdown markup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    /// Other entries of the YAML front matter.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, serde_json::Value>,
+    /// Whether the note was deleted from the server (kept with --keep-deleted).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
+    /// Why the last download of the note failed, if it did. The note is
+    /// downloaded again on the next update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<DownloadError>,
 }
 
-async fn build_database(team: &str) -> anyhow::Result<Vec<Page>> {
-    let client = reqwest::Client::builder()
-        .cookie_store(true)
-        .tcp_keepalive(Duration::new(60, 0))
-        .build()?;
-
-    // Retry up to 3 times with increasing intervals between attempts.
-    let retry_policy =
-        reqwest_retry::policies::ExponentialBackoff::builder().build_with_max_retries(3);
-
-    let client = reqwest_middleware::ClientBuilder::new(client)
-        .with(reqwest_retry::RetryTransientMiddleware::new_with_policy(
-            retry_policy,
-        ))
-        .build();
-
-    // Get CSRF token
-    // See: https://hackmd.io/@ystl/BkqNtYvrP
-    let response = client.get(SERVER_URL).send().await?;
-    let content = response.text().await?;
-    let re = Regex::new(r#""csrf-token" content="(.+)""#).unwrap();
-    let cap = re
-        .captures_iter(&content)
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("no CSRF token found"))?;
-    let csrf_token = String::from(&cap[1]);
-    println!("CSRF token: {}", &csrf_token);
-
-    // Login
-    let login_url = format!("{server}/login", server = SERVER_URL);
-    let mut params = HashMap::new();
-
-    print!("HackMD user: ");
-    std::io::stdout().flush()?;
-    let mut hackmd_user = String::new();
-    std::io::stdin()
-        .read_line(&mut hackmd_user)
-        .expect("error: unable to read user input");
-    let hackmd_pass = rpassword::prompt_password("HackMD password: ")?;
-
-    params.insert("email", hackmd_user.trim());
-    params.insert("password", &hackmd_pass);
-
-    let response = client
-        .post(&login_url)
-        .header("X-XSRF-Token", csrf_token)
-        .form(&params)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        anyhow::bail!("Login failure");
-    }
-
-    // Query
-    let request_url = format!(
-        "{server}/api/overview/team/{team}",
-        server = SERVER_URL,
-        team = team
-    );
-    let response = client.get(&request_url).send().await?.error_for_status()?;
-    let mut page_list: Vec<Page> = response.json().await?;
-
-    const CONCURRENT_REQUESTS: usize = 5;
-    let bodies = stream::iter(&page_list)
-        .map(|page| {
-            let client = &client;
-
-            println!("Downloading {}", page.id);
-            let page_url = format!("{server}/{id}/download", server = SERVER_URL, id = page.id);
+/// URL of the server hosting the notes: the one the database was synchronized
+/// with, or else `--server`.
+fn server_url<'a>(header: &'a Header, args: &'a Args) -> &'a str {
+    header
+        .server
+        .as_deref()
+        .unwrap_or(&args.server)
+        .trim_end_matches('/')
+}
 
-            async move {
-                let response = client.get(&page_url).send().await?;
-                response.text().await.map_err(anyhow::Error::new)
-            }
-        })
-        .buffered(CONCURRENT_REQUESTS);
-
-    bodies
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .enumerate()
-        .for_each(|(idx, text)| {
-            if let Ok(content) = text {
-                page_list[idx].content = Some(content);
-            }
+/// Search engine selected by the options: Meilisearch, the local index, or
+/// else the database itself, indexed in memory if it is a JSON file.
+fn search_backend(args: &Args) -> anyhow::Result<search::Backend> {
+    let database = Database::new(&args.database);
+    if let Some(url) = &args.meilisearch {
+        // The database is only needed to link the notes to their server.
+        let header = if database.exists() {
+            database.header()?
+        } else {
+            Header::default()
+        };
+        let server_url = server_url(&header, args);
+        return Ok(search::Backend::Meilisearch {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: args.meilisearch_key.clone(),
+            index: args.index.clone(),
+            primary_key: args.primary_key.clone(),
+            server_url: server_url.to_string(),
         });
+    }
+    // The local index stores the URLs of the notes.
+    if let Some(dir) = &args.index_dir {
+        return Ok(search::Backend::Local(local_index::open(Path::new(dir))?));
+    }
 
-    Ok(page_list)
-}
-
-async fn to_meilisearch(page_list: &[Page], url: &str) -> anyhow::Result<()> {
-    // Add documents to meilisearch
-    let search_client = meilisearch_sdk::client::Client::new(url, "masterKey");
-    let _health = search_client.health().await?;
-    let pages_index = search_client.get_index("pages").await;
-    let pages_index = match pages_index {
-        Ok(index) => index,
-        _ => {
-            let task = search_client.create_index("pages", None).await?;
-            let task = task.wait_for_completion(&search_client, None, None).await?;
-            let result = task.try_make_index(&search_client);
-            match result {
-                Ok(index) => index,
-                Err(task) => {
-                    return Err(
-                        meilisearch_sdk::errors::Error::Meilisearch(task.unwrap_failure()).into(),
-                    )
-                }
-            }
-        }
-    };
-    pages_index.add_or_replace(page_list, Some("id")).await?;
+    if let Database::Sqlite(path) = &database {
+        let connection = storage::sqlite::open_existing(path)?;
+        let header = storage::sqlite::load_header(&connection)?;
+        return Ok(search::Backend::Sqlite {
+            server_url: server_url(&header, args).to_string(),
+            connection: Mutex::new(connection),
+        });
+    }
 
-    Ok(())
+    info!("Loading HackMD database from {}", database);
+    let (header, mut page_list) = database.load()?;
+    frontmatter::fill_metadata(&mut page_list);
+    markdown::fill_text(&mut page_list);
+    let index = local_index::in_memory(&page_list, server_url(&header, args), args.sections)?;
+    Ok(search::Backend::Local(index))
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    // The logs would garble the terminal UI.
+    if !matches!(args.command, Some(Command::Tui(_))) {
+        logging::init(args.verbose, args.quiet, args.log_format);
+    }
+
+    let config = match &args.config {
+        Some(path) => Config::load(Path::new(path))?,
+        None => Config::default(),
+    };
 
     anyhow::ensure!(
         !args.database.is_empty(),
@@ -178,34 +346,161 @@ async fn main() -> anyhow::Result<()> {
             arg: "database".to_string()
         }
     );
+    meilisearch::check_primary_key(&args.primary_key)?;
 
-    let page_list = if args.update || !Path::new(&args.database).is_file() {
-        println!("Building HackMD database...");
+    if let Some(Command::Search(query)) = &args.command {
+        let hits = search_backend(&args)?.search(query).await?;
+        search::print(&hits);
+        return Ok(());
+    }
 
-        anyhow::ensure!(
-            args.team.is_some(),
-            UserInputError::MissingArgument {
-                arg: "team".to_string()
-            }
-        );
+    if let Some(Command::Tui(options)) = &args.command {
+        let backend = search_backend(&args)?;
+        return tui::run(&backend, options).await;
+    }
+
+    if let Some(Command::Serve(options)) = &args.command {
+        let backend = search_backend(&args)?;
+        return serve::run(backend, options).await;
+    }
+
+    let database = Database::new(&args.database);
+
+    if let Some(Command::Grep(grep)) = &args.command {
+        info!("Loading HackMD database from {}", database);
+        let (header, page_list) = database.load()?;
+        if !grep::run(&page_list, server_url(&header, &args), grep)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-        let page_list = build_database(&args.team.unwrap()).await?;
+    if let Some(Command::Export(export)) = &args.command {
+        info!("Loading HackMD database from {}", database);
+        let (header, mut page_list) = database.load()?;
+        frontmatter::fill_metadata(&mut page_list);
+        export::run(&page_list, server_url(&header, &args), export)?;
+        return Ok(());
+    }
 
-        println!("Dumping HackMD database to {}", args.database);
-        let f = File::create(args.database).expect("Unable to create file");
-        let f = BufWriter::new(f);
-        serde_json::to_writer(f, &page_list)?;
+    if let Some(Command::Import { path }) = &args.command {
+        info!("Importing HackMD database from {}", path);
+        let (header, mut page_list) = Database::Json(path.clone()).load()?;
+        frontmatter::fill_metadata(&mut page_list);
+        markdown::fill_text(&mut page_list);
+        info!("Dumping HackMD database to {}", database);
+        return database.save(&header, &page_list);
+    }
 
-        page_list
+    let mut failed = 0;
+    let (header, page_list) = if args.update || !database.exists() {
+        info!("Building HackMD database...");
+
+        let server = Server::new(&args.server, args.api_url.as_deref(), args.flavor);
+        let lookup = credentials::Lookup {
+            token: args.token.as_deref(),
+            token_file: args.token_file.as_deref().map(Path::new),
+            config_token: config.token.as_deref(),
+            user: args.user.as_deref(),
+            password_file: args.password_file.as_deref().map(Path::new),
+            keyring: args.keyring,
+        };
+        let auth = match lookup.token()? {
+            Some(token) => {
+                info!("Using HackMD API token from {}", token.source);
+                Auth::Token(token)
+            }
+            None => {
+                let (user, password) = lookup.login()?;
+                info!("Using HackMD password from {}", password.source);
+                Auth::Login { user, password }
+            }
+        };
+        let header = Header {
+            team: args.team.clone(),
+            server: Some(args.server.trim_end_matches('/').to_string()),
+            synced_at: Some(Utc::now().to_rfc3339()),
+        };
+        let previous = if database.exists() {
+            info!("Loading HackMD database from {}", database);
+            let (previous_header, previous) = database.load()?;
+            if previous_header.server.is_some()
+                && (previous_header.team != header.team || previous_header.server != header.server)
+            {
+                warn!(
+                    "{} was synchronized with team {} of {}",
+                    database,
+                    previous_header.team.as_deref().unwrap_or("-"),
+                    previous_header.server.as_deref().unwrap_or("-"),
+                );
+            }
+            previous
+        } else {
+            Vec::new()
+        };
+        // With --full, nothing is carried over but deleted notes are still
+        // detected.
+        let carried_over = if args.full { &[] } else { previous.as_slice() };
+        let (mut page_list, mut summary) = server
+            .build_database(args.team.as_deref(), &auth, carried_over)
+            .await?;
+        summary.deleted = sync::handle_deleted(&mut page_list, previous, args.keep_deleted);
+        info!("Synchronized notes: {}", summary);
+        failed = sync::report_failures(&page_list);
+        frontmatter::fill_metadata(&mut page_list);
+        markdown::fill_text(&mut page_list);
+
+        info!("Dumping HackMD database to {}", database);
+        database.save(&header, &page_list)?;
+
+        (header, page_list)
     } else {
-        println!("Loading HackMD database from {}", args.database);
-        let file = File::open(args.database)?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader)?
+        info!("Loading HackMD database from {}", database);
+        let (header, mut page_list) = database.load()?;
+        frontmatter::fill_metadata(&mut page_list);
+        markdown::fill_text(&mut page_list);
+        (header, page_list)
     };
 
-    if args.meilisearch.is_some() {
-        to_meilisearch(&page_list, &args.meilisearch.unwrap()).await?;
+    let server_url = server_url(&header, &args).to_string();
+
+    if let Some(dir) = &args.index_dir {
+        local_index::build(&page_list, Path::new(dir), &server_url, args.sections)?;
+    }
+
+    if let Some(dir) = &args.archive {
+        archive::update(&page_list, Path::new(dir), &server_url)?;
+    }
+
+    if let Some(url) = args.meilisearch {
+        let options = meilisearch::Options {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: args.meilisearch_key,
+            index: args.index,
+            primary_key: args.primary_key,
+            wait: !args.no_wait,
+            timeout: args.task_timeout.map(Duration::from_secs),
+            settings: config
+                .settings
+                .unwrap_or_else(meilisearch::IndexSettings::default_profile),
+            batch_size: args.batch_size.max(1),
+            batch_bytes: args.batch_max_bytes,
+            concurrency: args.upload_concurrency.max(1),
+            sections: if args.sections {
+                Some(server_url)
+            } else {
+                None
+            },
+        };
+        if args.rebuild {
+            meilisearch::rebuild(&page_list, &options).await?;
+        } else {
+            meilisearch::upload(&page_list, &options).await?;
+        }
+    }
+
+    if let Some(max) = args.max_failures {
+        anyhow::ensure!(failed <= max, SyncError::TooManyFailures { failed, max });
     }
 
     Ok(())
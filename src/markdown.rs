@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+
+use crate::frontmatter;
+use crate::Page;
+
+/// Renderer of Markdown notes to plain text, for indexing and snippets.
+pub struct Renderer {
+    /// Lines of HackMD-specific syntax, removed before rendering.
+    hackmd_lines: Vec<Regex>,
+    /// Inline HackMD-specific syntax, replaced before rendering.
+    hackmd_inline: Vec<(Regex, &'static str)>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer {
+            hackmd_lines: vec![
+                // Containers (`:::info` ... `:::`): keep their content only.
+                Regex::new(r"^[ \t]*:::.*$").unwrap(),
+                // Table of contents.
+                Regex::new(r"^[ \t]*\[TOC\][ \t]*$").unwrap(),
+            ],
+            hackmd_inline: vec![
+                // Embeds (`{%youtube id %}`, `{%hackmd id %}`...).
+                (Regex::new(r"\{%[^%\n]*%\}").unwrap(), ""),
+                // Highlighted text (`==mark==`).
+                (Regex::new(r"==([^=\n]+)==").unwrap(), "$1"),
+            ],
+        }
+    }
+
+    /// Render a note to plain text: headings, paragraphs, list items and code
+    /// blocks end up on their own lines, without any markup or HTML.
+    pub fn to_text(&self, markdown: &str) -> String {
+        self.sections(markdown)
+            .into_iter()
+            .map(|section| section.text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Remove the front matter and the HackMD-specific syntax of a note,
+    /// leaving standard Markdown.
+    pub fn clean(&self, markdown: &str) -> String {
+        let body = match frontmatter::split_front_matter(markdown) {
+            Some((_, body)) => body,
+            None => markdown,
+        };
+
+        // The code blocks and code spans are kept as is.
+        let mut source = String::with_capacity(body.len());
+        let mut fence: Option<&str> = None;
+        for line in body.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            match fence {
+                Some(marker) => {
+                    if trimmed.starts_with(marker) {
+                        fence = None;
+                    }
+                    source.push_str(line);
+                    continue;
+                }
+                None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                    fence = Some(&trimmed[..3]);
+                    source.push_str(line);
+                    continue;
+                }
+                None => {}
+            }
+
+            let content = line.trim_end_matches(&['\r', '\n'][..]);
+            if self.hackmd_lines.iter().any(|re| re.is_match(content)) {
+                source.push_str(&line[content.len()..]);
+                continue;
+            }
+            for (idx, segment) in line.split('`').enumerate() {
+                if idx > 0 {
+                    source.push('`');
+                }
+                if idx % 2 == 1 {
+                    source.push_str(segment);
+                    continue;
+                }
+                let mut segment = segment.to_string();
+                for (re, replacement) in &self.hackmd_inline {
+                    segment = re.replace_all(&segment, *replacement).into_owned();
+                }
+                source.push_str(&segment);
+            }
+        }
+        source
+    }
+
+    /// Split a note on its headings, and render each section to plain text.
+    ///
+    /// The text before the first heading, if any, makes up a first section
+    /// without heading.
+    pub fn sections(&self, markdown: &str) -> Vec<Section> {
+        let source = self.clean(markdown);
+
+        let mut sections = vec![Section::default()];
+        // Headings enclosing the current section, with their level.
+        let mut path: Vec<(usize, String)> = Vec::new();
+        let mut anchors = Anchors::default();
+        let mut heading: Option<String> = None;
+
+        for event in Parser::new_ext(&source, parser_options()) {
+            match event {
+                Event::Start(Tag::Heading(..)) => {
+                    heading = Some(String::new());
+                    continue;
+                }
+                Event::End(Tag::Heading(level, ..)) => {
+                    let title = heading.take().unwrap_or_default().trim().to_string();
+                    let level = level as usize;
+                    while matches!(path.last(), Some((parent, _)) if *parent >= level) {
+                        path.pop();
+                    }
+                    path.push((level, title.clone()));
+
+                    sections.push(Section {
+                        path: path.iter().map(|(_, title)| title.clone()).collect(),
+                        anchor: Some(anchors.unique(&title)),
+                        text: format!("{}\n", title),
+                    });
+                    continue;
+                }
+                _ => {}
+            }
+
+            let text = match &mut heading {
+                Some(heading) => heading,
+                None => &mut sections.last_mut().unwrap().text,
+            };
+            match event {
+                Event::Text(t) | Event::Code(t) => text.push_str(&t),
+                Event::SoftBreak => text.push(' '),
+                Event::HardBreak => text.push('\n'),
+                Event::End(Tag::TableCell) => text.push(' '),
+                Event::End(
+                    Tag::Paragraph
+                    | Tag::Item
+                    | Tag::CodeBlock(_)
+                    | Tag::TableHead
+                    | Tag::TableRow
+                    | Tag::FootnoteDefinition(_),
+                ) => text.push('\n'),
+                _ => {}
+            }
+        }
+
+        for section in &mut sections {
+            section.text = section
+                .text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+        if sections[0].text.is_empty() {
+            sections.remove(0);
+        }
+
+        sections
+    }
+}
+
+/// Markdown extensions supported by HackMD.
+pub fn parser_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options
+}
+
+/// Part of a note under a heading.
+#[derive(Debug, Default)]
+pub struct Section {
+    /// Titles of the enclosing headings, from the top level one to the
+    /// heading of this section.
+    pub path: Vec<String>,
+    /// Identifier of the heading in the HTML rendering of HackMD.
+    pub anchor: Option<String>,
+    pub text: String,
+}
+
+/// Generator of heading identifiers, the same way as HackMD: whitespace is
+/// replaced by dashes and punctuation is removed, and duplicates get a
+/// numbered suffix.
+#[derive(Default)]
+struct Anchors {
+    seen: HashMap<String, usize>,
+}
+
+impl Anchors {
+    fn slugify(title: &str) -> String {
+        title
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-")
+            .chars()
+            .filter(|c| !"!\"#$%&'()*+,./:;<=>?@[\\]^`{|}~".contains(*c))
+            .collect()
+    }
+
+    fn unique(&mut self, title: &str) -> String {
+        let slug = Anchors::slugify(title);
+        let count = self.seen.entry(slug.clone()).or_insert(0);
+        *count += 1;
+        match *count {
+            1 => slug,
+            n => format!("{}-{}", slug, n - 1),
+        }
+    }
+}
+
+/// Fill the plain text field of the pages from their content.
+pub fn fill_text(page_list: &mut [Page]) {
+    let renderer = Renderer::new();
+
+    for page in page_list.iter_mut() {
+        page.text = page
+            .content
+            .as_deref()
+            .map(|content| renderer.to_text(content));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_text(markdown: &str) -> String {
+        Renderer::new().to_text(markdown)
+    }
+
+    #[test]
+    fn blocks_are_on_their_own_lines() {
+        let markdown =
+            "# Title\n\nSome *text*\non two lines.\n\n- one\n- two\n\n```rust\nlet a = 1;\n```\n";
+
+        assert_eq!(
+            to_text(markdown),
+            "Title\nSome text on two lines.\none\ntwo\nlet a = 1;"
+        );
+    }
+
+    #[test]
+    fn front_matter_is_removed() {
+        assert_eq!(to_text("---\ntags: meeting\n---\n# Title\n"), "Title");
+    }
+
+    #[test]
+    fn containers_keep_their_content() {
+        let markdown = ":::info\nThis is **important**.\n:::\n\n:::spoiler Details\nHidden\n:::\n";
+
+        assert_eq!(to_text(markdown), "This is important.\nHidden");
+    }
+
+    #[test]
+    fn table_of_contents_is_removed() {
+        assert_eq!(to_text("[TOC]\n\n# Title\n\nText\n"), "Title\nText");
+    }
+
+    #[test]
+    fn embeds_are_removed() {
+        let markdown =
+            "Watch this:\n\n{%youtube dQw4w9WgXcQ %}\n\nand {%hackmd @team/abc %} that.\n";
+
+        assert_eq!(to_text(markdown), "Watch this:\nand  that.");
+    }
+
+    #[test]
+    fn marks_keep_their_text() {
+        assert_eq!(
+            to_text("This is ==highlighted== text."),
+            "This is highlighted text."
+        );
+    }
+
+    #[test]
+    fn code_is_kept_as_is() {
+        let markdown = "Check `a == b == c` and `{%x%}`.\n\n```\n:::info\nx ==y== z\n[TOC]\n```\n";
+
+        assert_eq!(
+            to_text(markdown),
+            "Check a == b == c and {%x%}.\n:::info\nx ==y== z\n[TOC]"
+        );
+    }
+
+    #[test]
+    fn tables_are_split_in_cells() {
+        let markdown = "| Name | Role |\n|------|------|\n| Alice | Dev |\n| Bob | Ops |\n";
+
+        assert_eq!(to_text(markdown), "Name Role\nAlice Dev\nBob Ops");
+    }
+
+    #[test]
+    fn html_is_removed() {
+        let markdown = "Some <b>bold</b> text<br>\n\n<div class=\"alert\">\n\nInside\n\n</div>\n";
+
+        assert_eq!(to_text(markdown), "Some bold text\nInside");
+    }
+
+    #[test]
+    fn anchors_match_hackmd() {
+        assert_eq!(Anchors::slugify("Meeting Notes"), "Meeting-Notes");
+        assert_eq!(Anchors::slugify("  What's new?  "), "Whats-new");
+        assert_eq!(Anchors::slugify("Version 1.2 (beta)"), "Version-12-beta");
+        assert_eq!(
+            Anchors::slugify("Step 1: install `helm`"),
+            "Step-1-install-helm"
+        );
+        assert_eq!(Anchors::slugify("日本語 の 見出し"), "日本語-の-見出し");
+        assert_eq!(Anchors::slugify("C++ & Rust"), "C--Rust");
+    }
+
+    #[test]
+    fn duplicate_anchors_are_numbered() {
+        let mut anchors = Anchors::default();
+
+        assert_eq!(anchors.unique("Notes"), "Notes");
+        assert_eq!(anchors.unique("Agenda"), "Agenda");
+        assert_eq!(anchors.unique("Notes"), "Notes-1");
+        assert_eq!(anchors.unique("Notes!"), "Notes-2");
+    }
+
+    #[test]
+    fn sections_follow_the_headings() {
+        let markdown = "Intro\n\n# Meeting\n\nText\n\n## Agenda\n\n- one\n\n### Details\n\n## Notes\n\nNotes text\n\n# Meeting `2`\n\n## Agenda\n";
+
+        let sections = Renderer::new().sections(markdown);
+
+        let summary: Vec<(Vec<&str>, Option<&str>, &str)> = sections
+            .iter()
+            .map(|section| {
+                (
+                    section.path.iter().map(String::as_str).collect(),
+                    section.anchor.as_deref(),
+                    section.text.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (vec![], None, "Intro"),
+                (vec!["Meeting"], Some("Meeting"), "Meeting\nText"),
+                (vec!["Meeting", "Agenda"], Some("Agenda"), "Agenda\none"),
+                (
+                    vec!["Meeting", "Agenda", "Details"],
+                    Some("Details"),
+                    "Details"
+                ),
+                (vec!["Meeting", "Notes"], Some("Notes"), "Notes\nNotes text"),
+                (vec!["Meeting 2"], Some("Meeting-2"), "Meeting 2"),
+                (vec!["Meeting 2", "Agenda"], Some("Agenda-1"), "Agenda"),
+            ]
+        );
+    }
+
+    #[test]
+    fn notes_starting_with_a_heading_have_no_intro() {
+        let sections = Renderer::new().sections("---\ntags: a\n---\n# Title\n\nText\n");
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].anchor.as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn links_keep_their_text() {
+        let markdown = "See [the docs](https://example.com/docs), ![a diagram](diagram.png) and <https://hackmd.io>.";
+
+        assert_eq!(
+            to_text(markdown),
+            "See the docs, a diagram and https://hackmd.io."
+        );
+    }
+}
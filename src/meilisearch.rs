@@ -0,0 +1,1119 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::errors::{Error, ErrorCode, ErrorType};
+use meilisearch_sdk::indexes::Index;
+use meilisearch_sdk::search::Selectors;
+use meilisearch_sdk::settings::Settings;
+use meilisearch_sdk::tasks::Task;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::search::{Hit, SearchArgs};
+use crate::sections;
+use crate::Page;
+
+#[derive(Error, Debug)]
+pub enum MeilisearchError {
+    #[error("Meilisearch rejected the API key, check --meilisearch-key: {0}")]
+    Unauthorized(String),
+    #[error("Meilisearch task {uid} ({what}) failed: {message}")]
+    TaskFailed {
+        uid: u64,
+        what: String,
+        message: String,
+    },
+    #[error("Meilisearch task {uid} ({what}) did not complete in time")]
+    TaskTimeout { uid: u64, what: String },
+    #[error("{failed} of {total} batches of documents could not be indexed")]
+    BatchesFailed { failed: usize, total: usize },
+    #[error("Meilisearch request failed with HTTP {status}: {message}")]
+    RequestFailed { status: u16, message: String },
+    #[error("Rebuilding the index needs Meilisearch 1.0 or later to swap indexes, not {0}")]
+    SwapUnsupported(String),
+    #[error("{0} cannot be the primary key, it is already an attribute of the documents")]
+    PrimaryKeyTaken(String),
+}
+
+/// Settings profile of the index. Unset fields keep their current value. The
+/// fields are snake_case in the configuration file, and camelCase once sent to
+/// Meilisearch.
+///
+/// See: https://docs.meilisearch.com/reference/features/settings.html
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all(serialize = "camelCase"))]
+pub struct IndexSettings {
+    /// Attributes searched for matches, by decreasing importance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub searchable_attributes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filterable_attributes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sortable_attributes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranking_rules: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_words: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synonyms: Option<HashMap<String, Vec<String>>>,
+}
+
+impl IndexSettings {
+    /// Profile used when none is given in the configuration file: titles
+    /// weigh more than the plain text of the contents, pages can be filtered
+    /// by tags and language, and sorted by last change.
+    pub fn default_profile() -> Self {
+        IndexSettings {
+            searchable_attributes: Some(vec![
+                "title".to_string(),
+                "breadcrumb".to_string(),
+                "tags".to_string(),
+                "description".to_string(),
+                "text".to_string(),
+            ]),
+            filterable_attributes: Some(vec![
+                "noteId".to_string(),
+                "tags".to_string(),
+                "lang".to_string(),
+            ]),
+            sortable_attributes: Some(vec!["lastchangeAt".to_string()]),
+            ..IndexSettings::default()
+        }
+    }
+
+    /// Whether applying this profile would change the `current` settings.
+    fn differs_from(&self, current: &Settings) -> bool {
+        fn differs<T: PartialEq>(wanted: &Option<T>, current: &Option<T>) -> bool {
+            wanted.is_some() && wanted != current
+        }
+        // Meilisearch returns these attributes sorted.
+        fn sorted(list: &Option<Vec<String>>) -> Option<Vec<String>> {
+            list.clone().map(|mut list| {
+                list.sort();
+                list
+            })
+        }
+
+        differs(&self.searchable_attributes, &current.searchable_attributes)
+            || differs(
+                &sorted(&self.filterable_attributes),
+                &sorted(&current.filterable_attributes),
+            )
+            || differs(
+                &sorted(&self.sortable_attributes),
+                &sorted(&current.sortable_attributes),
+            )
+            || differs(&self.ranking_rules, &current.ranking_rules)
+            || differs(&sorted(&self.stop_words), &sorted(&current.stop_words))
+            || differs(&self.synonyms, &current.synonyms)
+    }
+
+    fn to_settings(&self) -> Settings {
+        Settings {
+            searchable_attributes: self.searchable_attributes.clone(),
+            filterable_attributes: self.filterable_attributes.clone(),
+            sortable_attributes: self.sortable_attributes.clone(),
+            ranking_rules: self.ranking_rules.clone(),
+            stop_words: self.stop_words.clone(),
+            synonyms: self.synonyms.clone(),
+            ..Settings::default()
+        }
+    }
+}
+
+/// Where and how to index the pages.
+#[derive(Debug)]
+pub struct Options {
+    pub url: String,
+    pub api_key: String,
+    /// Name (uid) of the index.
+    pub index: String,
+    /// Attribute of the documents used as primary key.
+    pub primary_key: String,
+    /// Whether to wait for the indexing tasks to complete.
+    pub wait: bool,
+    /// How long to wait for each task, forever if `None`.
+    pub timeout: Option<Duration>,
+    pub settings: IndexSettings,
+    /// Maximum number of documents per batch.
+    pub batch_size: usize,
+    /// Maximum size of a batch, once serialized to JSON.
+    pub batch_bytes: usize,
+    /// Number of batches uploaded at the same time.
+    pub concurrency: usize,
+    /// Whether to index one document per section instead of one per page,
+    /// with the URL of the server to link to the sections.
+    pub sections: Option<String>,
+}
+
+/// Number of times a failed batch is uploaded again.
+const BATCH_RETRIES: usize = 3;
+
+/// Attributes of the documents of the notes and of the sections, besides
+/// their identifier.
+const DOCUMENT_ATTRIBUTES: &[&str] = &[
+    "noteId",
+    "title",
+    "breadcrumb",
+    "anchor",
+    "url",
+    "lastchangeAt",
+    "content",
+    "text",
+    "tags",
+    "description",
+    "lang",
+    "metadata",
+    "deleted",
+    "error",
+];
+
+/// Check that the primary key can hold the identifiers of the documents.
+pub fn check_primary_key(primary_key: &str) -> Result<(), MeilisearchError> {
+    if primary_key.is_empty() || DOCUMENT_ATTRIBUTES.contains(&primary_key) {
+        return Err(MeilisearchError::PrimaryKeyTaken(primary_key.to_string()));
+    }
+    Ok(())
+}
+
+/// Documents as sent to Meilisearch, with their identifier under the primary
+/// key instead of `id`.
+fn with_primary_key<T: Serialize>(
+    documents: &[T],
+    primary_key: &str,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    documents
+        .iter()
+        .map(|document| {
+            let mut value = serde_json::to_value(document)?;
+            if let Some(attributes) = value.as_object_mut() {
+                if let Some(id) = attributes.remove("id") {
+                    attributes.insert(primary_key.to_string(), id);
+                }
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Split the documents into batches of at most `max_count` documents and
+/// `max_bytes` bytes of JSON. A document bigger than `max_bytes` gets a batch
+/// of its own.
+fn batches<T: Serialize>(
+    documents: &[T],
+    max_count: usize,
+    max_bytes: usize,
+) -> anyhow::Result<Vec<Vec<&T>>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    // Account for the brackets of the JSON array.
+    let mut batch_bytes = 2;
+
+    for (idx, document) in documents.iter().enumerate() {
+        // Account for the separating comma.
+        let bytes = serde_json::to_vec(document)?.len() + 1;
+        if bytes > max_bytes {
+            warn!(
+                "Document {} is bigger than the batch size limit ({} bytes)",
+                idx, bytes
+            );
+        }
+        if !batch.is_empty() && (batch.len() >= max_count || batch_bytes + bytes > max_bytes) {
+            batches.push(std::mem::take(&mut batch));
+            batch_bytes = 2;
+        }
+        batch.push(document);
+        batch_bytes += bytes;
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    Ok(batches)
+}
+
+/// Turn authorization failures into a clearer error.
+fn check_auth(err: Error) -> anyhow::Error {
+    match err {
+        Error::Meilisearch(err)
+            if matches!(
+                err.error_code,
+                ErrorCode::InvalidApiKey | ErrorCode::MissingAuthorizationHeader
+            ) =>
+        {
+            MeilisearchError::Unauthorized(err.error_message).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// How long to wait for a task without `--task-timeout`. The SDK would give up
+/// after 5 seconds otherwise.
+const NO_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How often the tasks are checked while rebuilding the index.
+const TASK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Whether a batch that failed with this error may succeed if it is uploaded
+/// again, i.e. Meilisearch could not be reached or failed internally. Rejected
+/// documents are not sent again, nor batches whose task is still running.
+fn is_transient(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<Error>() {
+        Some(Error::Meilisearch(err)) => matches!(err.error_type, ErrorType::Internal),
+        Some(Error::UnreachableServer) | Some(Error::HttpError(_)) => true,
+        _ => false,
+    }
+}
+
+/// Wait for an enqueued task to complete, whether it succeeded or not.
+async fn completion(
+    client: &Client,
+    task: Task,
+    what: &str,
+    options: &Options,
+) -> anyhow::Result<Task> {
+    let uid = task.get_uid();
+    let timeout = options.timeout.unwrap_or(NO_TIMEOUT);
+    match task.wait_for_completion(client, None, Some(timeout)).await {
+        Ok(task) => Ok(task),
+        Err(Error::Timeout) => Err(MeilisearchError::TaskTimeout {
+            uid,
+            what: what.to_string(),
+        }
+        .into()),
+        Err(err) => Err(check_auth(err)),
+    }
+}
+
+/// Wait for an enqueued task to complete, and report its failure.
+async fn wait_for_task(
+    client: &Client,
+    task: Task,
+    what: &str,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let uid = task.get_uid();
+    if !options.wait {
+        info!("Enqueued Meilisearch task {} ({})", uid, what);
+        return Ok(());
+    }
+
+    debug!("Waiting for Meilisearch task {} ({})", uid, what);
+    let task = completion(client, task, what, options).await?;
+
+    if task.is_failure() {
+        let err = task.unwrap_failure();
+        return Err(MeilisearchError::TaskFailed {
+            uid,
+            what: what.to_string(),
+            message: format!("{} ({:?})", err.error_message, err.error_code),
+        }
+        .into());
+    }
+
+    info!("Meilisearch task {} ({}) succeeded", uid, what);
+    Ok(())
+}
+
+/// Apply the settings profile, unless the index already uses it.
+async fn apply_settings(
+    client: &Client,
+    index: &Index,
+    uid: &str,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let current = index.get_settings().await.map_err(check_auth)?;
+    if !options.settings.differs_from(&current) {
+        debug!("Meilisearch settings are up to date");
+        return Ok(());
+    }
+
+    info!("Updating Meilisearch settings of index {}", uid);
+    let task = index
+        .set_settings(&options.settings.to_settings())
+        .await
+        .map_err(check_auth)?;
+    wait_for_task(client, task, "update settings", options).await
+}
+
+async fn add_batch<T: Serialize>(
+    client: &Client,
+    index: &Index,
+    batch: &[&T],
+    what: &str,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let task = index
+        .add_or_replace(batch, Some(&options.primary_key))
+        .await
+        .map_err(check_auth)?;
+    wait_for_task(client, task, what, options).await
+}
+
+/// Add or replace the documents batch by batch, uploading a few batches at
+/// the same time. Batches that failed because of a transient error are
+/// uploaded again, up to `BATCH_RETRIES` times.
+async fn add_documents<T: Serialize>(
+    client: &Client,
+    index: &Index,
+    documents: &[T],
+    options: &Options,
+) -> anyhow::Result<()> {
+    let documents = with_primary_key(documents, &options.primary_key)?;
+    let batches = batches(&documents, options.batch_size, options.batch_bytes)?;
+    let total = batches.len();
+    info!(
+        "Indexing {} documents in {} batches",
+        documents.len(),
+        total
+    );
+
+    let mut pending: Vec<usize> = (0..total).collect();
+    let mut done = 0;
+    let mut failed = 0;
+    for attempt in 0..=BATCH_RETRIES {
+        if pending.is_empty() {
+            break;
+        }
+        if attempt > 0 {
+            warn!(
+                "Retrying {} failed batches (attempt {})",
+                pending.len(),
+                attempt
+            );
+        }
+
+        let results = stream::iter(pending)
+            .map(|idx| {
+                let batches = &batches;
+                let what = format!("add documents, batch {}/{}", idx + 1, total);
+                async move {
+                    (
+                        idx,
+                        add_batch(client, index, &batches[idx], &what, options).await,
+                    )
+                }
+            })
+            .buffer_unordered(options.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        pending = Vec::new();
+        for (idx, result) in results {
+            match result {
+                Ok(()) => {
+                    done += 1;
+                    info!(
+                        "Indexed batch {}/{} ({}/{} done)",
+                        idx + 1,
+                        total,
+                        done,
+                        total
+                    );
+                }
+                Err(err) => {
+                    warn!("Batch {}/{} failed: {:#}", idx + 1, total, err);
+                    if is_transient(&err) {
+                        pending.push(idx);
+                    } else {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let failed = failed + pending.len();
+    if failed > 0 {
+        return Err(MeilisearchError::BatchesFailed { failed, total }.into());
+    }
+
+    Ok(())
+}
+
+async fn get_or_create_index(
+    client: &Client,
+    uid: &str,
+    options: &Options,
+) -> anyhow::Result<Index> {
+    match client.get_index(uid).await {
+        Ok(index) => Ok(index),
+        Err(Error::Meilisearch(err)) if matches!(err.error_code, ErrorCode::IndexNotFound) => {
+            info!("Creating Meilisearch index {}", uid);
+            let task = client
+                .create_index(uid, Some(&options.primary_key))
+                .await
+                .map_err(check_auth)?;
+            let task = completion(client, task, "create index", options).await?;
+            match task.try_make_index(client) {
+                Ok(index) => Ok(index),
+                Err(task) => Err(Error::Meilisearch(task.unwrap_failure()).into()),
+            }
+        }
+        Err(err) => Err(check_auth(err)),
+    }
+}
+
+/// Add the documents of the pages, one per page or one per section.
+///
+/// Returns the identifiers of the documents, including the ones of the notes
+/// whose download failed, which are not sent again.
+async fn add_pages(
+    client: &Client,
+    index: &Index,
+    page_list: &[Page],
+    options: &Options,
+) -> anyhow::Result<HashSet<String>> {
+    // The notes whose download failed keep their current documents.
+    let failed: HashSet<&str> = page_list
+        .iter()
+        .filter(|page| page.error.is_some())
+        .map(|page| page.id.as_str())
+        .collect();
+
+    match &options.sections {
+        Some(server_url) => {
+            let documents = sections::split_pages(page_list, server_url);
+            let updated: Vec<_> = documents
+                .iter()
+                .filter(|document| !failed.contains(document.note_id))
+                .collect();
+            add_documents(client, index, &updated, options).await?;
+            Ok(documents.into_iter().map(|document| document.id).collect())
+        }
+        None => {
+            let documents: Vec<&Page> = page_list.iter().filter(|page| !page.deleted).collect();
+            let updated: Vec<_> = documents
+                .iter()
+                .filter(|page| !failed.contains(page.id.as_str()))
+                .collect();
+            add_documents(client, index, &updated, options).await?;
+            Ok(documents.iter().map(|page| page.id.clone()).collect())
+        }
+    }
+}
+
+/// Delete the documents of the index that are not in `keep`.
+async fn prune(
+    client: &Client,
+    index: &Index,
+    keep: &HashSet<String>,
+    options: &Options,
+) -> anyhow::Result<()> {
+    const PAGE_SIZE: usize = 1000;
+
+    let mut stale = Vec::new();
+    let mut offset = 0;
+    loop {
+        let documents: Vec<serde_json::Map<String, serde_json::Value>> = index
+            .get_documents(Some(offset), Some(PAGE_SIZE), Some(&options.primary_key))
+            .await
+            .map_err(check_auth)?;
+        let count = documents.len();
+
+        stale.extend(
+            documents
+                .into_iter()
+                .filter_map(|mut document| match document.remove(&options.primary_key) {
+                    Some(serde_json::Value::String(id)) => Some(id),
+                    _ => None,
+                })
+                .filter(|id| !keep.contains(id)),
+        );
+
+        if count < PAGE_SIZE {
+            break;
+        }
+        offset += count;
+    }
+
+    if !stale.is_empty() {
+        info!("Removing {} stale documents from Meilisearch", stale.len());
+        let task = index.delete_documents(&stale).await.map_err(check_auth)?;
+        wait_for_task(client, task, "delete stale documents", options).await?;
+    }
+
+    Ok(())
+}
+
+/// Add or replace the pages in the index, and remove the deleted ones.
+pub async fn upload(page_list: &[Page], options: &Options) -> anyhow::Result<()> {
+    let client = Client::new(options.url.as_str(), options.api_key.as_str());
+    let _health = client.health().await?;
+    let index = get_or_create_index(&client, &options.index, options).await?;
+    apply_settings(&client, &index, &options.index, options).await?;
+
+    let ids = add_pages(&client, &index, page_list, options).await?;
+
+    // The documents of the notes deleted from the server, whether during this
+    // update or an earlier one, are not part of the database anymore. The
+    // sections of a note also change with its content.
+    prune(&client, &index, &ids, options).await
+}
+
+/// Version of Meilisearch, as returned by `/version`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Version {
+    pkg_version: String,
+}
+
+/// Task enqueued by Meilisearch 1.x.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TaskInfo {
+    task_uid: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct TaskStatus {
+    status: String,
+    error: Option<ResponseError>,
+}
+
+/// Error of a request or of a task.
+#[derive(Deserialize, Debug)]
+struct ResponseError {
+    message: String,
+    code: String,
+}
+
+/// Client of the routes used to rebuild an index.
+///
+/// Swapping indexes needs Meilisearch 1.0, whose tasks cannot be read by the
+/// SDK, which targets Meilisearch 0.27: the rebuild calls the routes directly.
+struct RebuildClient<'a> {
+    http: reqwest::Client,
+    options: &'a Options,
+}
+
+impl<'a> RebuildClient<'a> {
+    fn new(options: &'a Options) -> Self {
+        RebuildClient {
+            http: reqwest::Client::new(),
+            options,
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.options.url, path))
+            .bearer_auth(&self.options.api_key)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> anyhow::Result<Response> {
+        check(request.send().await?).await
+    }
+
+    /// Refuse the versions of Meilisearch that cannot swap indexes.
+    async fn check_version(&self) -> anyhow::Result<()> {
+        let version: Version = self
+            .send(self.request(Method::GET, "/version"))
+            .await?
+            .json()
+            .await?;
+        let major = version
+            .pkg_version
+            .split('.')
+            .next()
+            .and_then(|major| major.parse::<u32>().ok());
+        if major < Some(1) {
+            return Err(MeilisearchError::SwapUnsupported(version.pkg_version).into());
+        }
+        Ok(())
+    }
+
+    /// Send a request creating a task, and return the uid of the task.
+    async fn enqueue(&self, request: RequestBuilder, what: &str) -> anyhow::Result<u64> {
+        let task: TaskInfo = self.send(request).await?.json().await?;
+        debug!("Enqueued Meilisearch task {} ({})", task.task_uid, what);
+        Ok(task.task_uid)
+    }
+
+    /// Wait for a task to complete, and report its failure.
+    async fn wait(&self, uid: u64, what: &str) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            let task: TaskStatus = self
+                .send(self.request(Method::GET, &format!("/tasks/{}", uid)))
+                .await?
+                .json()
+                .await?;
+            match task.status.as_str() {
+                "succeeded" => break,
+                "failed" | "canceled" => {
+                    let message = match task.error {
+                        Some(err) => format!("{} ({})", err.message, err.code),
+                        None => task.status,
+                    };
+                    return Err(MeilisearchError::TaskFailed {
+                        uid,
+                        what: what.to_string(),
+                        message,
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+
+            if matches!(self.options.timeout, Some(timeout) if start.elapsed() > timeout) {
+                return Err(MeilisearchError::TaskTimeout {
+                    uid,
+                    what: what.to_string(),
+                }
+                .into());
+            }
+            tokio::time::sleep(TASK_POLL_INTERVAL).await;
+        }
+
+        info!("Meilisearch task {} ({}) succeeded", uid, what);
+        Ok(())
+    }
+
+    async fn run(&self, request: RequestBuilder, what: &str) -> anyhow::Result<()> {
+        let uid = self.enqueue(request, what).await?;
+        self.wait(uid, what).await
+    }
+
+    async fn index_exists(&self, uid: &str) -> anyhow::Result<bool> {
+        let request = self.request(Method::GET, &format!("/indexes/{}", uid));
+        match request.send().await? {
+            response if response.status() == StatusCode::NOT_FOUND => Ok(false),
+            response => check(response).await.map(|_| true),
+        }
+    }
+
+    async fn create_index(&self, uid: &str) -> anyhow::Result<()> {
+        info!("Creating Meilisearch index {}", uid);
+        let request = self
+            .request(Method::POST, "/indexes")
+            .json(&serde_json::json!({
+                "uid": uid,
+                "primaryKey": self.options.primary_key,
+            }));
+        self.run(request, "create index").await
+    }
+
+    async fn delete_index(&self, uid: &str) -> anyhow::Result<()> {
+        let request = self.request(Method::DELETE, &format!("/indexes/{}", uid));
+        self.run(request, "delete index").await
+    }
+
+    /// Add the documents batch by batch. All the batches are enqueued before
+    /// waiting for them, so that Meilisearch can process them together.
+    async fn add_documents<T: Serialize>(&self, uid: &str, documents: &[T]) -> anyhow::Result<()> {
+        let options = self.options;
+        let documents = with_primary_key(documents, &options.primary_key)?;
+        let batches = batches(&documents, options.batch_size, options.batch_bytes)?;
+        let total = batches.len();
+        info!(
+            "Indexing {} documents in {} batches",
+            documents.len(),
+            total
+        );
+
+        let mut tasks = Vec::new();
+        for (idx, batch) in batches.iter().enumerate() {
+            let what = format!("add documents, batch {}/{}", idx + 1, total);
+            let request = self
+                .request(Method::POST, &format!("/indexes/{}/documents", uid))
+                .query(&[("primaryKey", &options.primary_key)])
+                .json(batch);
+            tasks.push((self.enqueue(request, &what).await?, what));
+        }
+        for (uid, what) in tasks {
+            self.wait(uid, &what).await?;
+        }
+        Ok(())
+    }
+
+    /// Fill a new index with the settings and the documents of all the
+    /// pages, and swap it with the live index.
+    async fn fill_and_swap(&self, tmp_uid: &str, page_list: &[Page]) -> anyhow::Result<()> {
+        let options = self.options;
+        info!("Applying Meilisearch settings to index {}", tmp_uid);
+        let request = self
+            .request(Method::PATCH, &format!("/indexes/{}/settings", tmp_uid))
+            .json(&options.settings);
+        self.run(request, "update settings").await?;
+
+        // The notes whose download failed are indexed with their previous
+        // content, since their current documents are not carried over.
+        match &options.sections {
+            Some(server_url) => {
+                let documents = sections::split_pages(page_list, server_url);
+                self.add_documents(tmp_uid, &documents).await?;
+            }
+            None => {
+                let documents: Vec<&Page> = page_list.iter().filter(|page| !page.deleted).collect();
+                self.add_documents(tmp_uid, &documents).await?;
+            }
+        }
+
+        // Both indexes must exist to be swapped.
+        if !self.index_exists(&options.index).await? {
+            self.create_index(&options.index).await?;
+        }
+        info!(
+            "Swapping Meilisearch indexes {} and {}",
+            options.index, tmp_uid
+        );
+        let request = self
+            .request(Method::POST, "/swap-indexes")
+            .json(&serde_json::json!([{ "indexes": [options.index, tmp_uid] }]));
+        self.run(request, "swap indexes").await
+    }
+}
+
+/// Turn the responses of Meilisearch that are not successful into errors.
+async fn check(response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let message = match response.json::<ResponseError>().await {
+        Ok(err) => format!("{} ({})", err.message, err.code),
+        Err(_) => status.to_string(),
+    };
+    if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+        return Err(MeilisearchError::Unauthorized(message).into());
+    }
+    Err(MeilisearchError::RequestFailed {
+        status: status.as_u16(),
+        message,
+    }
+    .into())
+}
+
+/// Rebuild the index from scratch without downtime: the pages are indexed
+/// in a temporary index, which is then swapped with the live one, so that
+/// searches see either the previous index or the new one as a whole. The
+/// previous content of the live index is deleted afterwards.
+///
+/// Needs Meilisearch 1.0 or later. The live index is left untouched if
+/// anything fails before the swap.
+pub async fn rebuild(page_list: &[Page], options: &Options) -> anyhow::Result<()> {
+    let client = RebuildClient::new(options);
+    client.check_version().await?;
+
+    let tmp_uid = format!("{}_tmp_{}", options.index, Utc::now().timestamp());
+    client.create_index(&tmp_uid).await?;
+    if let Err(err) = client.fill_and_swap(&tmp_uid, page_list).await {
+        warn!("Deleting temporary Meilisearch index {}", tmp_uid);
+        if let Err(e) = client.delete_index(&tmp_uid).await {
+            warn!("Unable to delete index {}: {:#}", tmp_uid, e);
+        }
+        return Err(err);
+    }
+
+    info!("Deleting previous Meilisearch index, now {}", tmp_uid);
+    client.delete_index(&tmp_uid).await
+}
+
+/// Number of words of the snippets.
+const SNIPPET_WORDS: usize = 30;
+
+/// Search result, either a note or a section.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SearchDocument {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    lastchange_at: String,
+    /// Only set for the sections.
+    url: Option<String>,
+    /// Only set for the notes.
+    content: Option<String>,
+    #[serde(default)]
+    text: String,
+    /// Other attributes, including the primary key.
+    #[serde(flatten)]
+    attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Search the index, linking the notes to the server.
+pub async fn search(
+    url: &str,
+    api_key: &str,
+    index: &str,
+    primary_key: &str,
+    server_url: &str,
+    args: &SearchArgs,
+) -> anyhow::Result<Vec<Hit>> {
+    let client = Client::new(url, api_key);
+    let index = client.index(index);
+    let text = args.text();
+    let sort: Vec<&str> = args.sort.iter().map(String::as_str).collect();
+
+    let mut query = index.search();
+    query
+        .with_query(&text)
+        .with_limit(args.limit)
+        .with_offset(args.offset)
+        .with_attributes_to_crop(Selectors::Some(&[("text", Some(SNIPPET_WORDS))]))
+        .with_attributes_to_highlight(Selectors::Some(&["text"]));
+    if let Some(filter) = &args.filter {
+        query.with_filter(filter);
+    }
+    if !sort.is_empty() {
+        query.with_sort(&sort);
+    }
+
+    let results = query
+        .execute::<SearchDocument>()
+        .await
+        .map_err(check_auth)?;
+    info!("Found {} results", results.nb_hits);
+
+    Ok(results
+        .hits
+        .into_iter()
+        .map(|hit| {
+            let snippet = hit
+                .formatted_result
+                .as_ref()
+                .and_then(|formatted| formatted.get("text"))
+                .and_then(|text| text.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let document = hit.result;
+            let url = match document.url {
+                Some(url) => url,
+                None => {
+                    let id = document.attributes.get(primary_key);
+                    let id = id.and_then(|id| id.as_str()).unwrap_or_default();
+                    format!("{}/{}", server_url, id)
+                }
+            };
+            Hit {
+                title: document.title,
+                snippet,
+                lastchange_at: document.lastchange_at,
+                url,
+                content: document.content.unwrap_or(document.text),
+            }
+        })
+        .collect())
+}
+
+/// Check that Meilisearch is available.
+pub async fn health(url: &str, api_key: &str) -> anyhow::Result<()> {
+    let client = Client::new(url, api_key);
+    let _health = client.health().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::extract::State;
+    use axum::http::{Method as HttpMethod, StatusCode as HttpStatusCode, Uri};
+    use axum::response::IntoResponse;
+    use axum::{Json, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::fetcher::mock;
+
+    /// Meilisearch server whose tasks all succeed, recording the requests.
+    struct MockMeilisearch {
+        version: &'static str,
+        requests: Mutex<Vec<String>>,
+    }
+
+    async fn handle(
+        State(mock): State<Arc<MockMeilisearch>>,
+        method: HttpMethod,
+        uri: Uri,
+    ) -> axum::response::Response {
+        let path = uri.path().to_string();
+        let mut requests = mock.requests.lock().unwrap();
+        if path == "/version" {
+            return Json(json!({ "pkgVersion": mock.version })).into_response();
+        }
+        if let Some(uid) = path.strip_prefix("/tasks/") {
+            return Json(json!({ "uid": uid.parse::<u64>().unwrap(), "status": "succeeded" }))
+                .into_response();
+        }
+        requests.push(format!("{} {}", method, path));
+        if method == HttpMethod::GET {
+            let error = json!({ "message": "Index not found", "code": "index_not_found" });
+            return (HttpStatusCode::NOT_FOUND, Json(error)).into_response();
+        }
+        let task_uid = requests.len();
+        (
+            HttpStatusCode::ACCEPTED,
+            Json(json!({ "taskUid": task_uid })),
+        )
+            .into_response()
+    }
+
+    async fn mock_meilisearch(version: &'static str) -> (Arc<MockMeilisearch>, Options) {
+        let mock = Arc::new(MockMeilisearch {
+            version,
+            requests: Mutex::new(Vec::new()),
+        });
+        let router = Router::new().fallback(handle).with_state(mock.clone());
+        let options = Options {
+            url: mock::serve(router).await,
+            api_key: String::new(),
+            index: "pages".to_string(),
+            primary_key: "id".to_string(),
+            wait: true,
+            timeout: None,
+            settings: IndexSettings::default_profile(),
+            batch_size: 1,
+            batch_bytes: 1000,
+            concurrency: 1,
+            sections: None,
+        };
+        (mock, options)
+    }
+
+    fn pages() -> Vec<Page> {
+        ["abc", "def"]
+            .iter()
+            .map(|id| Page {
+                id: id.to_string(),
+                title: id.to_string(),
+                content: Some(format!("# {}", id)),
+                ..Page::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rebuild_swaps_a_temporary_index() {
+        let (mock, options) = mock_meilisearch("1.2.0").await;
+
+        rebuild(&pages(), &options).await.unwrap();
+
+        let requests = mock.requests.lock().unwrap();
+        assert_eq!(requests[0], "POST /indexes");
+        let tmp_uid = requests
+            .iter()
+            .find_map(|request| request.strip_prefix("PATCH /indexes/"))
+            .and_then(|path| path.strip_suffix("/settings"))
+            .unwrap()
+            .to_string();
+        assert!(tmp_uid.starts_with("pages_tmp_"));
+        assert_eq!(
+            requests[1..],
+            [
+                format!("PATCH /indexes/{}/settings", tmp_uid),
+                format!("POST /indexes/{}/documents", tmp_uid),
+                format!("POST /indexes/{}/documents", tmp_uid),
+                "GET /indexes/pages".to_string(),
+                "POST /indexes".to_string(),
+                "POST /swap-indexes".to_string(),
+                format!("DELETE /indexes/{}", tmp_uid),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rebuild_needs_meilisearch_1() {
+        let (mock, options) = mock_meilisearch("0.27.2").await;
+
+        let err = rebuild(&pages(), &options).await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<MeilisearchError>(),
+            Some(MeilisearchError::SwapUnsupported(_))
+        ));
+        assert!(mock.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn documents_are_identified_by_the_primary_key() {
+        let documents = vec![json!({ "id": "abc", "title": "Notes" })];
+
+        assert_eq!(with_primary_key(&documents, "id").unwrap(), documents);
+        assert_eq!(
+            with_primary_key(&documents, "uid").unwrap(),
+            vec![json!({ "uid": "abc", "title": "Notes" })]
+        );
+        assert!(check_primary_key("uid").is_ok());
+        assert!(check_primary_key("noteId").is_err());
+        assert!(check_primary_key("").is_err());
+    }
+
+    #[test]
+    fn settings_are_sent_in_camel_case() {
+        let settings = IndexSettings {
+            sortable_attributes: Some(vec!["lastchangeAt".to_string()]),
+            ..IndexSettings::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&settings).unwrap(),
+            json!({ "sortableAttributes": ["lastchangeAt"] })
+        );
+    }
+
+    #[test]
+    fn batches_by_count() {
+        let documents = vec![1, 2, 3, 4, 5];
+
+        let batches = batches(&documents, 2, 1000).unwrap();
+
+        assert_eq!(batches, vec![vec![&1, &2], vec![&3, &4], vec![&5]]);
+    }
+
+    #[test]
+    fn batches_by_size() {
+        // Each string takes 5 bytes once serialized, plus a comma.
+        let documents = vec!["aaa", "bbb", "ccc"];
+
+        let batches = batches(&documents, 100, 2 + 6 * 2).unwrap();
+
+        assert_eq!(batches, vec![vec![&"aaa", &"bbb"], vec![&"ccc"]]);
+    }
+
+    #[test]
+    fn big_document_gets_its_own_batch() {
+        let documents = vec!["a", "too big to fit", "b"];
+
+        let batches = batches(&documents, 100, 10).unwrap();
+
+        assert_eq!(
+            batches,
+            vec![vec![&"a"], vec![&"too big to fit"], vec![&"b"]]
+        );
+    }
+
+    #[test]
+    fn batches_of_nothing() {
+        let documents: Vec<String> = Vec::new();
+
+        assert!(batches(&documents, 10, 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(is_transient(&Error::UnreachableServer.into()));
+        assert!(!is_transient(
+            &MeilisearchError::TaskTimeout {
+                uid: 1,
+                what: "add documents".to_string(),
+            }
+            .into()
+        ));
+        assert!(!is_transient(
+            &MeilisearchError::TaskFailed {
+                uid: 1,
+                what: "add documents".to_string(),
+                message: "invalid primary key".to_string(),
+            }
+            .into()
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::storage::sqlite;
+use crate::{local_index, meilisearch};
+
+/// Markers around the matches in the snippets, as returned by Meilisearch.
+pub const HIGHLIGHT_PRE: &str = "<em>";
+pub const HIGHLIGHT_POST: &str = "</em>";
+
+/// Errors due to the options of a search rather than to the search engine.
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("Invalid filter {filter:?}: {message}")]
+    InvalidFilter { filter: String, message: String },
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("{backend} can only be sorted by lastchangeAt, not {sort}")]
+    UnsupportedSort { backend: &'static str, sort: String },
+}
+
+/// Options of the `search` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct SearchArgs {
+    /// Words to search for.
+    #[clap(required = true)]
+    pub query: Vec<String>,
+
+    /// Maximum number of results.
+    #[clap(long, default_value = "20")]
+    pub limit: usize,
+
+    /// Number of results to skip, to get the next pages of results.
+    #[clap(long, default_value = "0")]
+    pub offset: usize,
+
+    /// Only return the documents matching a filter: a Meilisearch filter
+    /// (e.g. "tags = meeting"), or a query on the local index (e.g.
+    /// "tags:meeting").
+    #[clap(long)]
+    pub filter: Option<String>,
+
+    /// Sort the results on an attribute instead of relevance, e.g.
+    /// "lastchangeAt:desc". Can be repeated with Meilisearch.
+    #[clap(long, multiple_occurrences = true)]
+    pub sort: Vec<String>,
+
+    /// Whether the last word also matches the words it starts, unless it is
+    /// followed by a space, to search as you type. Meilisearch always does.
+    #[clap(skip)]
+    pub prefix: bool,
+}
+
+impl SearchArgs {
+    pub fn text(&self) -> String {
+        self.query.join(" ")
+    }
+}
+
+/// Result of a search, either a note or one of its sections.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Hit {
+    pub title: String,
+    /// Extract of the text around the matches, which are surrounded by
+    /// [`HIGHLIGHT_PRE`] and [`HIGHLIGHT_POST`].
+    pub snippet: String,
+    pub lastchange_at: String,
+    pub url: String,
+    /// Markdown content of the note, or text of the section.
+    #[serde(skip)]
+    pub content: String,
+}
+
+/// Search engine queried by the `search`, `tui` and `serve` subcommands.
+pub enum Backend {
+    Meilisearch {
+        url: String,
+        api_key: String,
+        index: String,
+        primary_key: String,
+        /// URL of the server, to link to the notes.
+        server_url: String,
+    },
+    Local(tantivy::Index),
+    /// Full-text index of a SQLite database.
+    Sqlite {
+        connection: Mutex<rusqlite::Connection>,
+        server_url: String,
+    },
+}
+
+impl Backend {
+    pub async fn search(&self, args: &SearchArgs) -> anyhow::Result<Vec<Hit>> {
+        match self {
+            Backend::Meilisearch {
+                url,
+                api_key,
+                index,
+                primary_key,
+                server_url,
+            } => meilisearch::search(url, api_key, index, primary_key, server_url, args).await,
+            Backend::Local(index) => local_index::search(index, args),
+            Backend::Sqlite {
+                connection,
+                server_url,
+            } => sqlite::search(&connection.lock().unwrap(), server_url, args),
+        }
+    }
+
+    /// Check that the search engine is available.
+    pub async fn health(&self) -> anyhow::Result<()> {
+        match self {
+            Backend::Meilisearch { url, api_key, .. } => meilisearch::health(url, api_key).await,
+            Backend::Local(_) | Backend::Sqlite { .. } => Ok(()),
+        }
+    }
+}
+
+/// Print the results on stdout, with the matches in bold if it is a
+/// terminal.
+pub fn print(hits: &[Hit]) {
+    let (bold, reset) = if std::io::stdout().is_terminal() {
+        ("\x1b[1m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+
+    for hit in hits {
+        let snippet = hit
+            .snippet
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(HIGHLIGHT_PRE, bold)
+            .replace(HIGHLIGHT_POST, reset);
+
+        println!("{}{}{}", bold, hit.title, reset);
+        println!("  {}  {}", hit.lastchange_at, hit.url);
+        if !snippet.is_empty() {
+            println!("  {}", snippet);
+        }
+        println!();
+    }
+}
@@ -0,0 +1,125 @@
+use serde::Serialize;
+
+use crate::markdown::Renderer;
+use crate::Page;
+
+/// Search document for a section of a note, with `--sections`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionDocument<'a> {
+    /// `<noteId>_<index of the section>`: Meilisearch identifiers only allow
+    /// alphanumeric characters, dashes and underscores, so the anchor cannot
+    /// be part of it.
+    pub id: String,
+    pub note_id: &'a str,
+    /// Title of the note.
+    pub title: &'a str,
+    /// Titles of the headings leading to the section.
+    pub breadcrumb: Vec<String>,
+    /// Identifier of the heading, empty for the text before the first one.
+    pub anchor: String,
+    /// Link to the section.
+    pub url: String,
+    pub lastchange_at: &'a str,
+    pub tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<&'a str>,
+    pub text: String,
+}
+
+/// Split the pages into one document per heading section.
+pub fn split_pages<'a>(page_list: &'a [Page], server_url: &str) -> Vec<SectionDocument<'a>> {
+    let renderer = Renderer::new();
+    let mut documents = Vec::new();
+
+    for page in page_list.iter().filter(|page| !page.deleted) {
+        let content = match &page.content {
+            Some(content) => content,
+            None => continue,
+        };
+
+        for (idx, section) in renderer.sections(content).into_iter().enumerate() {
+            let anchor = section.anchor.unwrap_or_default();
+            let url = if anchor.is_empty() {
+                format!("{}/{}", server_url, page.id)
+            } else {
+                format!("{}/{}#{}", server_url, page.id, anchor)
+            };
+
+            documents.push(SectionDocument {
+                id: format!("{}_{}", page.id, idx),
+                note_id: &page.id,
+                title: &page.title,
+                breadcrumb: section.path,
+                anchor,
+                url,
+                lastchange_at: &page.lastchange_at,
+                tags: &page.tags,
+                lang: page.lang.as_deref(),
+                text: section.text,
+            });
+        }
+    }
+
+    documents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str, content: Option<&str>) -> Page {
+        Page {
+            id: id.to_string(),
+            title: format!("Note {}", id),
+            lastchange_at: "2022-05-01T10:00:00Z".to_string(),
+            content: content.map(str::to_string),
+            tags: vec!["meeting".to_string()],
+            ..Page::default()
+        }
+    }
+
+    #[test]
+    fn one_document_per_section() {
+        let mut deleted = page("ghi", Some("# Deleted"));
+        deleted.deleted = true;
+        let page_list = vec![
+            page("abc", Some("Intro\n\n# Agenda\n\n## Budget\n\nText\n")),
+            page("def", None),
+            deleted,
+        ];
+
+        let documents = split_pages(&page_list, "https://hackmd.io");
+
+        let ids: Vec<&str> = documents.iter().map(|doc| doc.id.as_str()).collect();
+        assert_eq!(ids, ["abc_0", "abc_1", "abc_2"]);
+        let urls: Vec<&str> = documents.iter().map(|doc| doc.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://hackmd.io/abc",
+                "https://hackmd.io/abc#Agenda",
+                "https://hackmd.io/abc#Budget",
+            ]
+        );
+        assert_eq!(documents[0].anchor, "");
+        assert!(documents[0].breadcrumb.is_empty());
+        assert_eq!(documents[0].text, "Intro");
+        assert_eq!(documents[2].breadcrumb, ["Agenda", "Budget"]);
+        assert_eq!(documents[2].text, "Budget\nText");
+        assert!(documents
+            .iter()
+            .all(|doc| doc.note_id == "abc" && doc.title == "Note abc" && doc.tags == ["meeting"]));
+    }
+
+    #[test]
+    fn documents_are_numbered_from_the_first_heading_without_intro() {
+        let page_list = vec![page("abc", Some("# Agenda\n\n# Notes\n"))];
+
+        let documents = split_pages(&page_list, "https://hackmd.io");
+
+        let ids: Vec<&str> = documents.iter().map(|doc| doc.id.as_str()).collect();
+        assert_eq!(ids, ["abc_0", "abc_1"]);
+        assert_eq!(documents[0].url, "https://hackmd.io/abc#Agenda");
+    }
+}
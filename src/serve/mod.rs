@@ -0,0 +1,192 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use meilisearch_sdk::errors::{Error as MeilisearchSdkError, ErrorType};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::meilisearch::MeilisearchError;
+use crate::search::{Backend, Hit, SearchArgs, SearchError};
+
+/// Search page, querying `/api/search`.
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// Options of the `serve` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on.
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    /// Maximum number of results returned at once.
+    #[clap(long, default_value = "100")]
+    pub max_limit: usize,
+}
+
+struct AppState {
+    backend: Backend,
+    max_limit: usize,
+}
+
+/// Parameters of `/api/search`.
+#[derive(Deserialize, Debug)]
+struct SearchParams {
+    #[serde(default)]
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    filter: Option<String>,
+    /// Attributes to sort on, separated by commas (e.g. `lastchangeAt:desc`).
+    sort: Option<String>,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+#[derive(Serialize, Debug)]
+struct SearchResponse {
+    query: String,
+    limit: usize,
+    offset: usize,
+    /// The matches in the snippets are surrounded by `<em>` and `</em>`, the
+    /// rest of the snippets is not escaped.
+    hits: Vec<Hit>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    error: String,
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(ErrorResponse { error: message })).into_response()
+}
+
+/// Status of the response to a failed search: the client is only at fault
+/// when its query, filter or sort is invalid.
+fn status(err: &anyhow::Error) -> StatusCode {
+    if err.is::<SearchError>() {
+        return StatusCode::BAD_REQUEST;
+    }
+    match err.downcast_ref::<MeilisearchSdkError>() {
+        Some(MeilisearchSdkError::Meilisearch(err))
+            if matches!(err.error_type, ErrorType::InvalidRequest) =>
+        {
+            StatusCode::BAD_REQUEST
+        }
+        Some(MeilisearchSdkError::UnreachableServer) | Some(MeilisearchSdkError::HttpError(_)) => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        Some(_) => StatusCode::BAD_GATEWAY,
+        None if err.is::<MeilisearchError>() => StatusCode::BAD_GATEWAY,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    debug!("Search request: {:?}", params);
+    let args = SearchArgs {
+        query: vec![params.q.clone()],
+        limit: params.limit.min(state.max_limit),
+        offset: params.offset,
+        filter: params.filter.filter(|filter| !filter.is_empty()),
+        sort: params
+            .sort
+            .iter()
+            .flat_map(|sort| sort.split(','))
+            .map(str::trim)
+            .filter(|sort| !sort.is_empty())
+            .map(str::to_string)
+            .collect(),
+        // The search page searches as you type.
+        prefix: true,
+    };
+
+    match state.backend.search(&args).await {
+        Ok(hits) => Json(SearchResponse {
+            query: params.q,
+            limit: args.limit,
+            offset: args.offset,
+            hits,
+        })
+        .into_response(),
+        Err(e) => {
+            let status = status(&e);
+            if status != StatusCode::BAD_REQUEST {
+                warn!("Search failed: {}", e);
+            }
+            error(status, e.to_string())
+        }
+    }
+}
+
+async fn healthz(State(state): State<Arc<AppState>>) -> Response {
+    match state.backend.health().await {
+        Ok(()) => "ok".into_response(),
+        Err(e) => {
+            warn!("Search backend unavailable: {}", e);
+            error(StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+        }
+    }
+}
+
+/// Serve the search page and API until interrupted.
+pub async fn run(backend: Backend, args: &ServeArgs) -> anyhow::Result<()> {
+    let state = Arc::new(AppState {
+        backend,
+        max_limit: args.max_limit.max(1),
+    });
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/api/search", get(search))
+        .route("/healthz", get(healthz))
+        .with_state(state);
+
+    info!("Listening on http://{}", args.listen);
+    axum::Server::try_bind(&args.listen)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_invalid_searches_are_bad_requests() {
+        let invalid = SearchError::InvalidQuery("fts5: syntax error near \"(\"".to_string());
+        assert_eq!(status(&invalid.into()), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(&MeilisearchSdkError::UnreachableServer.into()),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(&MeilisearchError::Unauthorized("invalid key".to_string()).into()),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            status(&anyhow::anyhow!("disk I/O error")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
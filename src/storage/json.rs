@@ -0,0 +1,219 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use super::{check_version, Header, StorageError, SCHEMA_VERSION};
+use crate::Page;
+
+/// Content of the JSON file: the version of the format and the header,
+/// followed by the pages.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    #[serde(flatten)]
+    header: Header,
+    pages: T,
+}
+
+/// Upgrade a database to the current version of the format.
+fn migrate(path: &str, mut value: Value) -> anyhow::Result<Value> {
+    // Version 0 was a bare list of pages.
+    let mut version = match &value {
+        Value::Array(_) => 0,
+        _ => value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| StorageError::MissingVersion(path.to_string()))?,
+    };
+    check_version(path, version)?;
+
+    while version < u64::from(SCHEMA_VERSION) {
+        info!("Migrating {} from version {}", path, version);
+        value = match version {
+            0 => json!({ "version": 1, "pages": value }),
+            _ => unreachable!(),
+        };
+        version += 1;
+    }
+    Ok(value)
+}
+
+/// Header of a database, whose pages are skipped.
+#[derive(Deserialize)]
+struct HeaderEnvelope {
+    version: Option<u64>,
+    #[serde(flatten)]
+    header: Header,
+    /// Skipped without being stored.
+    #[serde(default, rename = "pages")]
+    _pages: IgnoredAny,
+}
+
+/// Read the header of the database without loading its pages.
+pub fn load_header(path: &str) -> anyhow::Result<Header> {
+    let data = fs::read(path)?;
+    // Version 0 was a bare list of pages, without header.
+    if data.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'[') {
+        return Ok(Header::default());
+    }
+    let envelope: HeaderEnvelope = serde_json::from_slice(&data)?;
+    let version = envelope
+        .version
+        .ok_or_else(|| StorageError::MissingVersion(path.to_string()))?;
+    check_version(path, version)?;
+    Ok(envelope.header)
+}
+
+pub fn load(path: &str) -> anyhow::Result<(Header, Vec<Page>)> {
+    let value: Value = if path == "-" {
+        serde_json::from_reader(BufReader::new(std::io::stdin()))?
+    } else {
+        let file = File::open(path)?;
+        serde_json::from_reader(BufReader::new(file))?
+    };
+    let envelope: Envelope<Vec<Page>> = serde_json::from_value(migrate(path, value)?)?;
+    Ok((envelope.header, envelope.pages))
+}
+
+/// Path next to the database, with a suffix appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Write the pages to a file and make sure they reached the disk and can be
+/// read back.
+fn write(path: &Path, header: &Header, page_list: &[Page]) -> anyhow::Result<()> {
+    let envelope = Envelope {
+        version: SCHEMA_VERSION,
+        header: header.clone(),
+        pages: page_list,
+    };
+    let mut f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut f, &envelope)?;
+    f.flush()?;
+    f.into_inner()?.sync_all()?;
+
+    let (_, written) = load(&path.to_string_lossy())
+        .with_context(|| format!("Invalid database written to {}", path.display()))?;
+    if written.len() != page_list.len() {
+        anyhow::bail!(
+            "Invalid database written to {}: {} pages instead of {}",
+            path.display(),
+            written.len(),
+            page_list.len()
+        );
+    }
+    Ok(())
+}
+
+/// Save the pages atomically: they are written to a temporary file in the
+/// same directory, which then replaces the database. The previous database is
+/// kept with a `.bak` suffix.
+pub fn save(path: &str, header: &Header, page_list: &[Page]) -> anyhow::Result<()> {
+    let path = Path::new(path);
+    let tmp = sibling(path, ".tmp");
+    if let Err(e) = write(&tmp, header, page_list) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    if path.is_file() {
+        let bak = sibling(path, ".bak");
+        debug!("Backing up {} to {}", path.display(), bak.display());
+        fs::copy(path, &bak)
+            .with_context(|| format!("Unable to back up the database to {}", bak.display()))?;
+    }
+    fs::rename(&tmp, path)?;
+
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_list_of_pages_is_migrated() {
+        let pages = json!([{ "id": "abc", "title": "Notes", "lastchangeAt": "2022-05-01" }]);
+
+        let value = migrate("hackmd.json", pages.clone()).unwrap();
+
+        assert_eq!(value, json!({ "version": 1, "pages": pages }));
+        let envelope: Envelope<Vec<Page>> = serde_json::from_value(value).unwrap();
+        assert_eq!(envelope.header, Header::default());
+        assert_eq!(envelope.pages[0].id, "abc");
+    }
+
+    #[test]
+    fn current_version_is_kept() {
+        let value = json!({ "version": 1, "team": "acme", "pages": [] });
+
+        assert_eq!(migrate("hackmd.json", value.clone()).unwrap(), value);
+    }
+
+    #[test]
+    fn header_is_read_without_the_pages() {
+        let path = std::env::temp_dir().join(format!("hackmd-search-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let header = Header {
+            server: Some("https://md.example.com".to_string()),
+            ..Header::default()
+        };
+        let page_list = vec![Page {
+            id: "abc".to_string(),
+            ..Page::default()
+        }];
+
+        save(path, &header, &page_list).unwrap();
+        assert_eq!(load_header(path).unwrap(), header);
+        fs::write(path, serde_json::to_vec(&page_list).unwrap()).unwrap();
+        assert_eq!(load_header(path).unwrap(), Header::default());
+
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(sibling(Path::new(path), ".bak"));
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let value = json!({ "version": 2, "pages": [] });
+
+        let err = migrate("hackmd.json", value).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::UnsupportedVersion { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn missing_version_is_refused() {
+        for value in [
+            json!({ "pages": [] }),
+            json!({ "version": "1", "pages": [] }),
+        ] {
+            let err = migrate("hackmd.json", value).unwrap_err();
+
+            assert!(matches!(
+                err.downcast_ref::<StorageError>(),
+                Some(StorageError::MissingVersion(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Page;
+
+mod json;
+pub mod sqlite;
+
+/// Version of the database format. Older databases are migrated when they
+/// are loaded, newer ones are refused.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error(
+        "{path} uses version {version} of the database format, but only versions up to \
+         {supported} are supported: please upgrade hackmd-search"
+    )]
+    UnsupportedVersion {
+        path: String,
+        version: u64,
+        supported: u32,
+    },
+    #[error("{0} has no valid database format version")]
+    MissingVersion(String),
+    #[error("The database {0} does not exist")]
+    NotFound(String),
+}
+
+fn check_version(path: &str, version: u64) -> Result<(), StorageError> {
+    if version > u64::from(SCHEMA_VERSION) {
+        return Err(StorageError::UnsupportedVersion {
+            path: path.to_string(),
+            version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Description of the database, stored along with the pages.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Header {
+    /// Team the notes come from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    /// URL of the HackMD server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// When the notes were last synchronized with the server (RFC 3339).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<String>,
+}
+
+/// Database of the notes, stored either as a JSON file or as a SQLite
+/// database, depending on the extension of its path (`.db`, `.sqlite` or
+/// `.sqlite3` for SQLite).
+pub enum Database {
+    /// JSON file, or stdin when the path is `-`.
+    Json(String),
+    Sqlite(String),
+}
+
+impl Database {
+    pub fn new(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("db" | "sqlite" | "sqlite3") => Database::Sqlite(path.to_string()),
+            _ => Database::Json(path.to_string()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Database::Json(path) | Database::Sqlite(path) => path,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path() == "-" || Path::new(self.path()).is_file()
+    }
+
+    pub fn load(&self) -> anyhow::Result<(Header, Vec<Page>)> {
+        match self {
+            Database::Json(path) => json::load(path),
+            Database::Sqlite(path) => sqlite::load(path),
+        }
+    }
+
+    /// Header of the database, without loading the pages. Since stdin can
+    /// only be read once, its header is empty.
+    pub fn header(&self) -> anyhow::Result<Header> {
+        match self {
+            Database::Json(path) if path == "-" => Ok(Header::default()),
+            Database::Json(path) => json::load_header(path),
+            Database::Sqlite(path) => sqlite::load_header(&sqlite::open_existing(path)?),
+        }
+    }
+
+    pub fn save(&self, header: &Header, page_list: &[Page]) -> anyhow::Result<()> {
+        match self {
+            Database::Json(path) => json::save(path, header, page_list),
+            Database::Sqlite(path) => sqlite::save(path, header, page_list),
+        }
+    }
+}
+
+impl fmt::Display for Database {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path() {
+            "-" => write!(f, "stdin"),
+            path => write!(f, "{}", path),
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use tracing::{debug, info};
+
+use super::{check_version, Header, StorageError};
+use crate::search::{Hit, SearchArgs, SearchError, HIGHLIGHT_POST, HIGHLIGHT_PRE};
+use crate::Page;
+
+/// Tables of the database:
+/// - `notes`: the notes as downloaded, with their plain text rendering,
+/// - `metadata`: tags, description, language and other front matter entries
+///   of the notes, as JSON values,
+/// - `sync_state`: header of the database (team, server and time of the last
+///   update),
+/// - `notes_fts`: full-text index of the notes, for offline searches. Its rows
+///   have the same rowid as the notes, which is declared so that it never
+///   changes, even on `VACUUM`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS notes (
+    rowid INTEGER PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    lastchange_at TEXT NOT NULL,
+    content TEXT,
+    text TEXT,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    error TEXT
+);
+CREATE TABLE IF NOT EXISTS metadata (
+    note_id TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (note_id, key)
+);
+CREATE TABLE IF NOT EXISTS sync_state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5 (title, tags, text);
+";
+
+/// Statements upgrading the database from each version of the format to the
+/// next one, starting from an empty database (version 0), up to
+/// `SCHEMA_VERSION`. The version of the database is stored in its
+/// `user_version`.
+const MIGRATIONS: &[&str] = &[SCHEMA];
+
+/// Number of tokens of the snippets.
+const SNIPPET_TOKENS: usize = 30;
+
+/// Keys of the `sync_state` table holding the header.
+const TEAM: &str = "team";
+const SERVER: &str = "server";
+const SYNCED_AT: &str = "synced_at";
+
+fn migrate(path: &str, conn: &mut Connection) -> anyhow::Result<()> {
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    check_version(path, u64::from(version))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        if version > 0 {
+            info!("Migrating {} from version {}", path, version);
+        }
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn open(path: &str) -> anyhow::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    migrate(path, &mut conn)?;
+    Ok(conn)
+}
+
+/// Open an existing database, which SQLite would otherwise create empty.
+pub fn open_existing(path: &str) -> anyhow::Result<Connection> {
+    if !Path::new(path).is_file() {
+        return Err(StorageError::NotFound(path.to_string()).into());
+    }
+    open(path)
+}
+
+pub fn load_header(conn: &Connection) -> anyhow::Result<Header> {
+    let get = |key: &str| {
+        conn.query_row(
+            "SELECT value FROM sync_state WHERE key = ?1",
+            [key],
+            |row| row.get(0),
+        )
+        .optional()
+    };
+    Ok(Header {
+        team: get(TEAM)?,
+        server: get(SERVER)?,
+        synced_at: get(SYNCED_AT)?,
+    })
+}
+
+/// Entries of the `metadata` table for a page.
+fn metadata(page: &Page) -> anyhow::Result<Vec<(&str, String)>> {
+    let mut entries = Vec::new();
+    if !page.tags.is_empty() {
+        entries.push(("tags", serde_json::to_string(&page.tags)?));
+    }
+    if let Some(description) = &page.description {
+        entries.push(("description", serde_json::to_string(description)?));
+    }
+    if let Some(lang) = &page.lang {
+        entries.push(("lang", serde_json::to_string(lang)?));
+    }
+    for (key, value) in &page.metadata {
+        entries.push((key, serde_json::to_string(value)?));
+    }
+    Ok(entries)
+}
+
+pub fn load(path: &str) -> anyhow::Result<(Header, Vec<Page>)> {
+    let conn = open_existing(path)?;
+    let header = load_header(&conn)?;
+
+    let mut page_list = Vec::new();
+    let mut stmt = conn.prepare(
+        "SELECT id, title, lastchange_at, content, text, deleted, error
+         FROM notes ORDER BY rowid",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let error: Option<String> = row.get(6)?;
+        page_list.push(Page {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            lastchange_at: row.get(2)?,
+            content: row.get(3)?,
+            text: row.get(4)?,
+            deleted: row.get(5)?,
+            error: error.map(|e| serde_json::from_str(&e)).transpose()?,
+            ..Page::default()
+        });
+    }
+
+    let positions: HashMap<String, usize> = page_list
+        .iter()
+        .enumerate()
+        .map(|(idx, page)| (page.id.clone(), idx))
+        .collect();
+    let mut stmt = conn.prepare("SELECT note_id, key, value FROM metadata")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let note_id: String = row.get(0)?;
+        let key: String = row.get(1)?;
+        let value: Value = serde_json::from_str(&row.get::<_, String>(2)?)?;
+        let page = match positions.get(&note_id) {
+            Some(&idx) => &mut page_list[idx],
+            None => continue,
+        };
+        match key.as_str() {
+            "tags" => page.tags = serde_json::from_value(value)?,
+            "description" => page.description = serde_json::from_value(value)?,
+            "lang" => page.lang = serde_json::from_value(value)?,
+            _ => {
+                page.metadata.insert(key, value);
+            }
+        }
+    }
+
+    Ok((header, page_list))
+}
+
+/// Update the database with the pages, in a single transaction: only the
+/// notes that changed are written, and the ones that are not part of the
+/// pages anymore are removed.
+pub fn save(path: &str, header: &Header, page_list: &[Page]) -> anyhow::Result<()> {
+    let mut conn = open(path)?;
+    let tx = conn.transaction()?;
+    let mut changed = 0;
+
+    {
+        let mut upsert = tx.prepare(
+            "INSERT INTO notes (id, title, lastchange_at, content, text, deleted, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (id) DO UPDATE SET
+                 title = excluded.title,
+                 lastchange_at = excluded.lastchange_at,
+                 content = excluded.content,
+                 text = excluded.text,
+                 deleted = excluded.deleted,
+                 error = excluded.error
+             WHERE notes.title IS NOT excluded.title
+                 OR notes.lastchange_at IS NOT excluded.lastchange_at
+                 OR notes.content IS NOT excluded.content
+                 OR notes.text IS NOT excluded.text
+                 OR notes.deleted IS NOT excluded.deleted
+                 OR notes.error IS NOT excluded.error
+             RETURNING rowid",
+        )?;
+        let mut delete_metadata = tx.prepare("DELETE FROM metadata WHERE note_id = ?1")?;
+        let mut insert_metadata =
+            tx.prepare("INSERT INTO metadata (note_id, key, value) VALUES (?1, ?2, ?3)")?;
+        let mut delete_fts = tx.prepare("DELETE FROM notes_fts WHERE rowid = ?1")?;
+        let mut insert_fts =
+            tx.prepare("INSERT INTO notes_fts (rowid, title, tags, text) VALUES (?1, ?2, ?3, ?4)")?;
+
+        for page in page_list {
+            let error = page.error.as_ref().map(serde_json::to_string).transpose()?;
+            // Nothing is returned when the note did not change.
+            let rowid: i64 = match upsert
+                .query_row(
+                    params![
+                        page.id,
+                        page.title,
+                        page.lastchange_at,
+                        page.content,
+                        page.text,
+                        page.deleted,
+                        error,
+                    ],
+                    |row| row.get(0),
+                )
+                .optional()?
+            {
+                Some(rowid) => rowid,
+                None => continue,
+            };
+            changed += 1;
+
+            delete_metadata.execute([&page.id])?;
+            for (key, value) in metadata(page)? {
+                insert_metadata.execute(params![page.id, key, value])?;
+            }
+            delete_fts.execute([rowid])?;
+            if !page.deleted {
+                insert_fts.execute(params![rowid, page.title, page.tags.join(" "), page.text])?;
+            }
+        }
+
+        let ids: HashSet<&str> = page_list.iter().map(|page| page.id.as_str()).collect();
+        let stale: Vec<i64> = tx
+            .prepare("SELECT rowid, id FROM notes")?
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|(_, id)| !ids.contains(id.as_str()))
+            .map(|(rowid, _)| rowid)
+            .collect();
+        for rowid in &stale {
+            // The metadata is removed along with the note.
+            tx.execute("DELETE FROM notes WHERE rowid = ?1", [rowid])?;
+            delete_fts.execute([rowid])?;
+        }
+        debug!(
+            "{} notes written to the database, {} removed",
+            changed,
+            stale.len()
+        );
+
+        for (key, value) in [
+            (TEAM, &header.team),
+            (SERVER, &header.server),
+            (SYNCED_AT, &header.synced_at),
+        ] {
+            match value {
+                Some(value) => tx.execute(
+                    "INSERT OR REPLACE INTO sync_state (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )?,
+                None => tx.execute("DELETE FROM sync_state WHERE key = ?1", [key])?,
+            };
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Full-text query for the words of a search, restricted by the filter if any.
+/// When searching as you type, the last word also matches as a prefix like
+/// with Meilisearch, unless it is followed by a space.
+fn match_expression(text: &str, filter: Option<&str>, prefix: bool) -> Option<String> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+
+    let mut expression = words.join(" ");
+    if prefix && !text.ends_with(char::is_whitespace) {
+        expression.push('*');
+    }
+    Some(match filter {
+        Some(filter) => format!("({}) AND ({})", expression, filter),
+        None => expression,
+    })
+}
+
+/// Turn the errors of SQLite about the full-text query, e.g. a syntax error or
+/// an unknown column in the filter, into a clearer error.
+fn check_query(err: rusqlite::Error) -> anyhow::Error {
+    match err {
+        rusqlite::Error::SqliteFailure(_, Some(message))
+            if message.starts_with("fts5:") || message.starts_with("no such column") =>
+        {
+            SearchError::InvalidQuery(message).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// Search the notes with the full-text index of the database.
+pub fn search(conn: &Connection, server_url: &str, args: &SearchArgs) -> anyhow::Result<Vec<Hit>> {
+    let expression = match match_expression(&args.text(), args.filter.as_deref(), args.prefix) {
+        Some(expression) => expression,
+        None => return Ok(Vec::new()),
+    };
+    // Matches in the titles weigh twice as much.
+    let order = match args.sort.as_slice() {
+        [] => "bm25(notes_fts, 2.0, 1.0, 1.0)",
+        [sort] if sort == "lastchangeAt" || sort == "lastchangeAt:asc" => "notes.lastchange_at ASC",
+        [sort] if sort == "lastchangeAt:desc" => "notes.lastchange_at DESC",
+        sort => {
+            return Err(SearchError::UnsupportedSort {
+                backend: "The database",
+                sort: sort.join(", "),
+            }
+            .into())
+        }
+    };
+
+    let total: usize = conn
+        .query_row(
+            "SELECT count(*) FROM notes_fts WHERE notes_fts MATCH ?1",
+            [&expression],
+            |row| row.get(0),
+        )
+        .map_err(check_query)?;
+    info!("Found {} results", total);
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT notes.id, notes.title, notes.lastchange_at,
+             snippet(notes_fts, 2, ?2, ?3, '…', ?4),
+             coalesce(notes.content, notes.text, '')
+         FROM notes_fts JOIN notes ON notes.rowid = notes_fts.rowid
+         WHERE notes_fts MATCH ?1
+         ORDER BY {}
+         LIMIT ?5 OFFSET ?6",
+        order
+    ))?;
+    let hits = stmt
+        .query_map(
+            params![
+                expression,
+                HIGHLIGHT_PRE,
+                HIGHLIGHT_POST,
+                SNIPPET_TOKENS,
+                args.limit,
+                args.offset
+            ],
+            |row| {
+                let id: String = row.get(0)?;
+                Ok(Hit {
+                    title: row.get(1)?,
+                    lastchange_at: row.get(2)?,
+                    snippet: row.get(3)?,
+                    content: row.get(4)?,
+                    url: format!("{}/{}", server_url, id),
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::DownloadError;
+
+    /// Database in the temporary directory, removed when dropped.
+    struct TempDatabase(PathBuf);
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "hackmd-search-{}-{}.db",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_file(&path);
+            TempDatabase(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn page(id: &str, lastchange_at: &str, text: &str) -> Page {
+        Page {
+            id: id.to_string(),
+            title: id.to_string(),
+            lastchange_at: lastchange_at.to_string(),
+            content: Some(text.to_string()),
+            text: Some(text.to_string()),
+            ..Page::default()
+        }
+    }
+
+    fn search_args(query: &str, filter: Option<&str>, sort: &[&str]) -> SearchArgs {
+        SearchArgs {
+            query: vec![query.to_string()],
+            limit: 20,
+            offset: 0,
+            filter: filter.map(str::to_string),
+            sort: sort.iter().map(|sort| sort.to_string()).collect(),
+            prefix: false,
+        }
+    }
+
+    fn titles(hits: &[Hit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.title.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_round_trip() {
+        let db = TempDatabase::new("round-trip");
+        let mut notes = page("abc", "2022-05-01T10:00:00Z", "Meeting notes");
+        notes.tags = vec!["meeting".to_string(), "weekly".to_string()];
+        notes.description = Some("Weekly meeting".to_string());
+        notes.lang = Some("en".to_string());
+        notes
+            .metadata
+            .insert("robots".to_string(), json!("noindex"));
+        let mut failed = page("def", "2022-05-02T10:00:00Z", "Old content");
+        failed.error = Some(DownloadError {
+            status: Some(500),
+            message: "Internal Server Error".to_string(),
+        });
+        let mut deleted = page("ghi", "2022-05-03T10:00:00Z", "Deleted");
+        deleted.deleted = true;
+        let page_list = vec![notes, failed, deleted];
+
+        save(db.path(), &Header::default(), &page_list).unwrap();
+        let (_, loaded) = load(db.path()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&page_list).unwrap()
+        );
+    }
+
+    #[test]
+    fn header_is_kept() {
+        let db = TempDatabase::new("header");
+        let header = Header {
+            team: Some("acme".to_string()),
+            server: Some("https://md.example.com".to_string()),
+            synced_at: Some("2022-05-01T10:00:00+00:00".to_string()),
+        };
+
+        save(db.path(), &header, &[]).unwrap();
+        assert_eq!(load(db.path()).unwrap().0, header);
+
+        let header = Header {
+            synced_at: None,
+            ..header
+        };
+        save(db.path(), &header, &[]).unwrap();
+        assert_eq!(load(db.path()).unwrap().0, header);
+    }
+
+    #[test]
+    fn only_changed_notes_are_written() {
+        let db = TempDatabase::new("unchanged");
+        let mut page_list = vec![
+            page("abc", "2022-05-01T10:00:00Z", "kubernetes"),
+            page("def", "2022-05-01T10:00:00Z", "kubernetes"),
+        ];
+        save(db.path(), &Header::default(), &page_list).unwrap();
+        // Rows that are written again get indexed again.
+        open(db.path())
+            .unwrap()
+            .execute("DELETE FROM notes_fts", [])
+            .unwrap();
+
+        page_list[1].lastchange_at = "2022-05-02T10:00:00Z".to_string();
+        save(db.path(), &Header::default(), &page_list).unwrap();
+
+        let conn = open(db.path()).unwrap();
+        let hits = search(&conn, "", &search_args("kubernetes", None, &[])).unwrap();
+        assert_eq!(titles(&hits), ["def"]);
+    }
+
+    #[test]
+    fn stale_notes_are_removed() {
+        let db = TempDatabase::new("stale");
+        let mut removed = page("def", "2022-05-01T10:00:00Z", "kubernetes");
+        removed.tags = vec!["meeting".to_string()];
+        let page_list = vec![page("abc", "2022-05-01T10:00:00Z", "helm"), removed];
+        save(db.path(), &Header::default(), &page_list).unwrap();
+
+        save(db.path(), &Header::default(), &page_list[..1]).unwrap();
+
+        let (_, loaded) = load(db.path()).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, "abc");
+        let conn = open(db.path()).unwrap();
+        let metadata: usize = conn
+            .query_row("SELECT count(*) FROM metadata", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(metadata, 0);
+        assert!(search(&conn, "", &search_args("kubernetes", None, &[]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let db = TempDatabase::new("version");
+        save(db.path(), &Header::default(), &[]).unwrap();
+        open(db.path())
+            .unwrap()
+            .pragma_update(None, "user_version", 99)
+            .unwrap();
+
+        let err = load(db.path()).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::UnsupportedVersion { version: 99, .. })
+        ));
+    }
+
+    #[test]
+    fn missing_database_is_refused() {
+        let db = TempDatabase::new("missing");
+
+        let err = open_existing(db.path()).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<StorageError>(),
+            Some(StorageError::NotFound(_))
+        ));
+        assert!(!db.0.exists());
+    }
+
+    #[test]
+    fn search_notes() {
+        let db = TempDatabase::new("search");
+        let mut meeting = page(
+            "abc",
+            "2022-05-02T10:00:00Z",
+            "Upgrade the kubernetes cluster",
+        );
+        meeting.tags = vec!["meeting".to_string()];
+        let page_list = vec![
+            meeting,
+            page("def", "2022-05-01T10:00:00Z", "Kubernetes cheat sheet"),
+            page("ghi", "2022-05-03T10:00:00Z", "Helm charts"),
+        ];
+        save(db.path(), &Header::default(), &page_list).unwrap();
+        let conn = open(db.path()).unwrap();
+
+        let hits = search(
+            &conn,
+            "https://hackmd.io",
+            &search_args("kubernetes", None, &[]),
+        )
+        .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].url, format!("https://hackmd.io/{}", hits[0].title));
+        assert!(hits[0].snippet.contains("<em>"));
+
+        let args = search_args("kubernetes", None, &["lastchangeAt:desc"]);
+        assert_eq!(titles(&search(&conn, "", &args).unwrap()), ["abc", "def"]);
+        let args = search_args("kubernetes", None, &["lastchangeAt"]);
+        assert_eq!(titles(&search(&conn, "", &args).unwrap()), ["def", "abc"]);
+
+        let args = search_args("kubernetes", Some("tags:meeting"), &[]);
+        assert_eq!(titles(&search(&conn, "", &args).unwrap()), ["abc"]);
+
+        let mut args = search_args("kube", None, &["lastchangeAt"]);
+        assert!(search(&conn, "", &args).unwrap().is_empty());
+        args.prefix = true;
+        assert_eq!(titles(&search(&conn, "", &args).unwrap()), ["def", "abc"]);
+        let mut args = search_args("kube ", None, &[]);
+        args.prefix = true;
+        assert!(search(&conn, "", &args).unwrap().is_empty());
+
+        let args = search_args("kubernetes", Some("tags:("), &[]);
+        let err = search(&conn, "", &args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SearchError>(),
+            Some(SearchError::InvalidQuery(_))
+        ));
+    }
+}
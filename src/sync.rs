@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use tracing::warn;
+
+use crate::Page;
+
+/// Comparison between the fresh overview of the server and the database.
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    /// Notes that were not in the database.
+    pub added: usize,
+    /// Notes that changed since the last sync.
+    pub changed: usize,
+    /// Notes whose download failed during the last sync.
+    pub retried: usize,
+    /// Notes whose content was carried over from the database.
+    pub kept: usize,
+    /// Identifiers of the notes that disappeared from the server.
+    pub deleted: Vec<String>,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} retried, {} kept, {} deleted",
+            self.added,
+            self.changed,
+            self.retried,
+            self.kept,
+            self.deleted.len()
+        )
+    }
+}
+
+/// Copy the content of the notes that did not change since the previous sync,
+/// so that only the other ones need to be downloaded.
+pub fn carry_over(page_list: &mut [Page], previous: &[Page]) -> SyncSummary {
+    let previous: HashMap<&str, &Page> = previous
+        .iter()
+        .map(|page| (page.id.as_str(), page))
+        .collect();
+    let mut summary = SyncSummary::default();
+
+    for page in page_list.iter_mut() {
+        match previous.get(page.id.as_str()) {
+            Some(old)
+                if !page.lastchange_at.is_empty()
+                    && old.lastchange_at == page.lastchange_at
+                    && old.content.is_some()
+                    && old.error.is_none() =>
+            {
+                page.content = old.content.clone();
+                summary.kept += 1;
+            }
+            Some(old) if old.error.is_some() => summary.retried += 1,
+            Some(_) => summary.changed += 1,
+            None => summary.added += 1,
+        }
+    }
+
+    summary
+}
+
+/// Find the notes of the previous database that are not on the server anymore.
+///
+/// With `keep_deleted`, they are added back to `page_list` as tombstones,
+/// otherwise they are simply dropped. Returns the identifiers of the notes
+/// deleted since the previous sync.
+pub fn handle_deleted(
+    page_list: &mut Vec<Page>,
+    previous: Vec<Page>,
+    keep_deleted: bool,
+) -> Vec<String> {
+    let current: HashSet<String> = page_list.iter().map(|page| page.id.clone()).collect();
+    let mut deleted = Vec::new();
+
+    for mut page in previous {
+        if current.contains(&page.id) {
+            continue;
+        }
+        if !page.deleted {
+            deleted.push(page.id.clone());
+        }
+        if keep_deleted {
+            page.deleted = true;
+            page_list.push(page);
+        }
+    }
+
+    deleted
+}
+
+/// Print the notes whose download failed, and return how many there are.
+pub fn report_failures(page_list: &[Page]) -> usize {
+    let failures: Vec<&Page> = page_list
+        .iter()
+        .filter(|page| page.error.is_some())
+        .collect();
+
+    for page in &failures {
+        if let Some(error) = &page.error {
+            warn!(id = %page.id, "Failed to download note: {}", error);
+        }
+    }
+
+    failures.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str, lastchange_at: &str, content: Option<&str>) -> Page {
+        Page {
+            id: id.to_string(),
+            title: id.to_string(),
+            lastchange_at: lastchange_at.to_string(),
+            content: content.map(str::to_string),
+            ..Page::default()
+        }
+    }
+
+    #[test]
+    fn carry_over_unchanged_notes() {
+        let mut previous = vec![
+            page("kept", "2022-01-01", Some("kept content")),
+            page("changed", "2022-01-01", Some("old content")),
+            page("retried", "2022-01-01", None),
+        ];
+        let mut page_list = vec![
+            page("kept", "2022-01-01", None),
+            page("changed", "2022-02-01", None),
+            page("retried", "2022-01-01", None),
+            page("added", "2022-01-01", None),
+        ];
+        previous[2].error = Some(crate::DownloadError {
+            status: Some(500),
+            message: "Internal Server Error".to_string(),
+        });
+
+        let summary = carry_over(&mut page_list, &previous);
+
+        assert_eq!(
+            (
+                summary.added,
+                summary.changed,
+                summary.retried,
+                summary.kept
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(page_list[0].content.as_deref(), Some("kept content"));
+        assert!(page_list[1].content.is_none());
+        assert!(page_list[2].content.is_none());
+        assert!(page_list[3].content.is_none());
+    }
+
+    #[test]
+    fn carry_over_needs_last_change_time() {
+        let previous = vec![page("note", "", Some("content"))];
+        let mut page_list = vec![page("note", "", None)];
+
+        let summary = carry_over(&mut page_list, &previous);
+
+        assert_eq!(summary.changed, 1);
+        assert!(page_list[0].content.is_none());
+    }
+
+    #[test]
+    fn drop_deleted_notes() {
+        let previous = vec![page("kept", "", None), page("deleted", "", None)];
+        let mut page_list = vec![page("kept", "", None)];
+
+        let deleted = handle_deleted(&mut page_list, previous, false);
+
+        assert_eq!(deleted, vec!["deleted"]);
+        assert_eq!(page_list.len(), 1);
+    }
+
+    #[test]
+    fn keep_deleted_notes_as_tombstones() {
+        let mut tombstone = page("tombstone", "", None);
+        tombstone.deleted = true;
+        let previous = vec![page("deleted", "", None), tombstone];
+        let mut page_list = vec![page("kept", "", None)];
+
+        let deleted = handle_deleted(&mut page_list, previous, true);
+
+        // Only the notes deleted since the previous sync are reported.
+        assert_eq!(deleted, vec!["deleted"]);
+        let ids: Vec<(&str, bool)> = page_list
+            .iter()
+            .map(|page| (page.id.as_str(), page.deleted))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![("kept", false), ("deleted", true), ("tombstone", true)]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use base64::Engine;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+
+use crate::markdown::Renderer;
+use crate::search::{Backend, Hit, SearchArgs};
+
+mod preview;
+
+/// Delay without keystroke after which the query is sent.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Number of lines scrolled in the preview pane with PageUp and PageDown.
+const PAGE_LINES: u16 = 10;
+
+const HELP: &str = "Enter: open  Ctrl-Y: copy URL  PgUp/PgDn: scroll  Esc: quit";
+
+/// Options of the `tui` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct TuiArgs {
+    /// Maximum number of results.
+    #[clap(long, default_value = "50")]
+    pub limit: usize,
+
+    /// Only show the documents matching a filter, as with the search
+    /// subcommand.
+    #[clap(long)]
+    pub filter: Option<String>,
+
+    /// Sort the results on an attribute instead of relevance, as with the
+    /// search subcommand.
+    #[clap(long, multiple_occurrences = true)]
+    pub sort: Vec<String>,
+}
+
+/// Puts the terminal in raw mode on an alternate screen, and restores it when
+/// dropped, including on errors.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// What to do after a keystroke.
+enum Action {
+    Nothing,
+    Search,
+    Quit,
+}
+
+struct App {
+    query: String,
+    hits: Vec<Hit>,
+    list: ListState,
+    /// Rendering of the selected note.
+    preview: Vec<Line<'static>>,
+    scroll: u16,
+    /// Message of the status bar: number of results, errors...
+    status: String,
+    renderer: Renderer,
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            query: String::new(),
+            hits: Vec::new(),
+            list: ListState::default(),
+            preview: Vec::new(),
+            scroll: 0,
+            status: "Type to search".to_string(),
+            renderer: Renderer::new(),
+        }
+    }
+
+    fn selected(&self) -> Option<&Hit> {
+        self.list.selected().and_then(|idx| self.hits.get(idx))
+    }
+
+    fn select(&mut self, idx: Option<usize>) {
+        self.list.select(idx);
+        self.scroll = 0;
+        self.preview = match self.selected() {
+            Some(hit) => preview::render(&self.renderer, &hit.content),
+            None => Vec::new(),
+        };
+    }
+
+    /// Move the selection by `offset` results, within the list.
+    fn move_selection(&mut self, offset: isize) {
+        if self.hits.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let idx = (current + offset).clamp(0, self.hits.len() as isize - 1);
+        self.select(Some(idx as usize));
+    }
+
+    async fn search(&mut self, backend: &Backend, args: &TuiArgs) {
+        if self.query.trim().is_empty() {
+            self.hits.clear();
+            self.select(None);
+            self.status = "Type to search".to_string();
+            return;
+        }
+
+        let search = SearchArgs {
+            query: vec![self.query.clone()],
+            limit: args.limit,
+            offset: 0,
+            filter: args.filter.clone(),
+            sort: args.sort.clone(),
+            prefix: true,
+        };
+        match backend.search(&search).await {
+            Ok(hits) => {
+                self.status = format!("{} results", hits.len());
+                self.hits = hits;
+                self.select(if self.hits.is_empty() { None } else { Some(0) });
+            }
+            Err(e) => self.status = format!("Error: {}", e),
+        }
+    }
+
+    fn open(&mut self) {
+        if let Some(hit) = self.selected() {
+            let url = hit.url.clone();
+            self.status = match open::that_detached(&url) {
+                Ok(()) => format!("Opened {}", url),
+                Err(e) => format!("Unable to open {}: {}", url, e),
+            };
+        }
+    }
+
+    /// Copy the URL of the selected note to the clipboard, with the OSC 52
+    /// escape sequence supported by most terminals (even through SSH).
+    fn copy(&mut self) {
+        if let Some(hit) = self.selected() {
+            let url = hit.url.clone();
+            let encoded = base64::engine::general_purpose::STANDARD.encode(&url);
+            let mut stdout = io::stdout();
+            self.status =
+                match write!(stdout, "\x1b]52;c;{}\x07", encoded).and_then(|_| stdout.flush()) {
+                    Ok(()) => format!("Copied {}", url),
+                    Err(e) => format!("Unable to copy {}: {}", url, e),
+                };
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if ctrl => return Action::Quit,
+            KeyCode::Enter => self.open(),
+            KeyCode::Char('o') if ctrl => self.open(),
+            KeyCode::Char('y') if ctrl => self.copy(),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Char('p') if ctrl => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('n') if ctrl => self.move_selection(1),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(PAGE_LINES),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(PAGE_LINES),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                return Action::Search;
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                return Action::Search;
+            }
+            KeyCode::Char(c) if !ctrl && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.query.push(c);
+                return Action::Search;
+            }
+            _ => {}
+        }
+        Action::Nothing
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input, body, status] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.size());
+        let [results, preview] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(body);
+
+        frame.render_widget(
+            Paragraph::new(self.query.as_str())
+                .block(Block::default().borders(Borders::ALL).title("Search")),
+            input,
+        );
+        frame.set_cursor(input.x + 1 + self.query.chars().count() as u16, input.y + 1);
+
+        let items: Vec<ListItem> = self
+            .hits
+            .iter()
+            .map(|hit| {
+                ListItem::new(vec![
+                    Line::from(Span::styled(
+                        hit.title.as_str(),
+                        Style::new().add_modifier(Modifier::BOLD),
+                    )),
+                    Line::from(Span::styled(
+                        format!("{}  {}", hit.lastchange_at, hit.url),
+                        Style::new().fg(Color::DarkGray),
+                    )),
+                ])
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Results"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, results, &mut self.list);
+
+        let title = self
+            .list
+            .selected()
+            .and_then(|idx| self.hits.get(idx))
+            .map(|hit| hit.title.clone())
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(Text::from(self.preview.clone()))
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0)),
+            preview,
+        );
+
+        frame.render_widget(
+            Paragraph::new(format!("{}  |  {}", self.status, HELP))
+                .style(Style::new().fg(Color::DarkGray)),
+            status,
+        );
+    }
+}
+
+/// Run the interactive search until the user quits.
+pub async fn run(backend: &Backend, args: &TuiArgs) -> anyhow::Result<()> {
+    let _guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut app = App::new();
+    // Whether the query changed since the last search.
+    let mut pending = false;
+
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        // The query is sent once the user stops typing.
+        if event::poll(DEBOUNCE)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match app.handle_key(key) {
+                    Action::Nothing => {}
+                    Action::Search => pending = true,
+                    Action::Quit => break,
+                }
+            }
+        } else if pending {
+            pending = false;
+            app.search(backend, args).await;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+
+use crate::markdown::{self, Renderer};
+
+/// Builder of the styled lines of the preview pane.
+#[derive(Default)]
+struct Preview {
+    lines: Vec<Line<'static>>,
+    /// Spans of the line being built.
+    current: Vec<Span<'static>>,
+    /// Styles of the enclosing inline elements (emphasis, links...).
+    styles: Vec<Style>,
+    /// Depth of block quotes.
+    quotes: usize,
+    /// Enclosing lists, with the number of the next item if they are ordered.
+    lists: Vec<Option<u64>>,
+    in_code_block: bool,
+}
+
+impl Preview {
+    fn style(&self) -> Style {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn push_style(&mut self, style: Style) {
+        self.styles.push(self.style().patch(style));
+    }
+
+    fn push(&mut self, text: &str, style: Style) {
+        self.current.push(Span::styled(text.to_string(), style));
+    }
+
+    /// End the current line.
+    fn flush(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        let mut spans = Vec::new();
+        if self.quotes > 0 {
+            spans.push(Span::styled(
+                "│ ".repeat(self.quotes),
+                Style::new().fg(Color::DarkGray),
+            ));
+        }
+        spans.append(&mut self.current);
+        self.lines.push(Line::from(spans));
+    }
+
+    /// End the current block with an empty line.
+    fn end_block(&mut self) {
+        self.flush();
+        if self.lines.last().is_some_and(|line| !line.spans.is_empty()) {
+            self.lines.push(Line::default());
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading(level, ..) => {
+                self.flush();
+                self.push_style(Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+                self.push(&format!("{} ", "#".repeat(level as usize)), self.style());
+            }
+            Tag::BlockQuote => {
+                self.flush();
+                self.quotes += 1;
+            }
+            Tag::CodeBlock(_) => {
+                self.flush();
+                self.in_code_block = true;
+                self.push_style(Style::new().fg(Color::Green));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}{}. ", indent, *number - 1)
+                    }
+                    _ => format!("{}• ", indent),
+                };
+                self.push(&marker, Style::new().fg(Color::DarkGray));
+            }
+            Tag::FootnoteDefinition(label) => {
+                self.flush();
+                self.push(&format!("[^{}]: ", label), Style::new().fg(Color::DarkGray));
+            }
+            Tag::Emphasis => self.push_style(Style::new().add_modifier(Modifier::ITALIC)),
+            Tag::Strong => self.push_style(Style::new().add_modifier(Modifier::BOLD)),
+            Tag::Strikethrough => self.push_style(Style::new().add_modifier(Modifier::CROSSED_OUT)),
+            Tag::Link(..) | Tag::Image(..) => self.push_style(
+                Style::new()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => {
+                if self.lists.is_empty() {
+                    self.end_block();
+                } else {
+                    self.flush();
+                }
+            }
+            Tag::Heading(..) => {
+                self.styles.pop();
+                self.end_block();
+            }
+            Tag::BlockQuote => {
+                self.flush();
+                self.quotes -= 1;
+                self.end_block();
+            }
+            Tag::CodeBlock(_) => {
+                self.styles.pop();
+                self.in_code_block = false;
+                self.end_block();
+            }
+            Tag::List(_) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                }
+            }
+            Tag::Item | Tag::TableHead | Tag::TableRow => self.flush(),
+            Tag::Table(_) | Tag::FootnoteDefinition(_) => self.end_block(),
+            Tag::TableCell => self.push(" │ ", Style::new().fg(Color::DarkGray)),
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..) => {
+                self.styles.pop();
+            }
+        }
+    }
+}
+
+/// Render a note to styled lines for the preview pane.
+pub fn render(renderer: &Renderer, content: &str) -> Vec<Line<'static>> {
+    let source = renderer.clean(content);
+    let mut preview = Preview::default();
+
+    for event in Parser::new_ext(&source, markdown::parser_options()) {
+        match event {
+            Event::Start(tag) => preview.start(tag),
+            Event::End(tag) => preview.end(tag),
+            Event::Text(text) if preview.in_code_block => {
+                for line in text.lines() {
+                    preview.push(line, preview.style());
+                    preview.flush();
+                }
+            }
+            Event::Text(text) => preview.push(&text, preview.style()),
+            Event::Code(code) => preview.push(&code, preview.style().fg(Color::Cyan)),
+            Event::FootnoteReference(label) => {
+                preview.push(&format!("[^{}]", label), Style::new().fg(Color::DarkGray))
+            }
+            Event::TaskListMarker(checked) => {
+                preview.push(if checked { "[x] " } else { "[ ] " }, preview.style())
+            }
+            Event::SoftBreak => preview.push(" ", preview.style()),
+            Event::HardBreak => preview.flush(),
+            Event::Rule => {
+                preview.flush();
+                preview.push(&"─".repeat(40), Style::new().fg(Color::DarkGray));
+                preview.end_block();
+            }
+            Event::Html(_) => {}
+        }
+    }
+    preview.flush();
+
+    preview.lines
+}